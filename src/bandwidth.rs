@@ -0,0 +1,124 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    static VIDEO_BYTES: Cell<u64>;
+}
+
+/// Bytes downloaded over the whole run
+static TOTAL_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Account downloaded bytes to the run total and, if called within [`collect`], to the current video
+pub fn record(bytes: u64) {
+    TOTAL_BYTES.fetch_add(bytes, Ordering::Relaxed);
+    let _ = VIDEO_BYTES.try_with(|video_bytes| video_bytes.set(video_bytes.get() + bytes));
+}
+
+/// Run a future, summing up all bytes recorded while it runs
+pub async fn collect<F: Future>(future: F) -> (F::Output, u64) {
+    VIDEO_BYTES.scope(Cell::new(0), async {
+        let output = future.await;
+        (output, VIDEO_BYTES.with(Cell::get))
+    }).await
+}
+
+/// Bytes downloaded so far in this run
+pub fn total() -> u64 {
+    TOTAL_BYTES.load(Ordering::Relaxed)
+}
+
+/// A token bucket limiting the download rate of everything that shares it
+///
+/// The bucket holds up to a second worth of bytes, so short bursts pass without waiting.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that may be downloaded right away, negative while downloads are ahead of the limit
+    available: f64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            bucket: Mutex::new(Bucket {
+                available: bytes_per_sec as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Account for `bytes` that were just downloaded, waiting until the rate is back under the
+    /// limit
+    ///
+    /// The bucket stays locked while waiting, so concurrent downloads queue up behind each other
+    /// instead of overshooting the limit together.
+    pub async fn consume(&self, bytes: u64) {
+        let mut bucket = self.bucket.lock().await;
+        let rate = self.bytes_per_sec as f64;
+        let now = Instant::now();
+        bucket.available = (bucket.available + now.duration_since(bucket.refilled).as_secs_f64() * rate).min(rate);
+        bucket.refilled = now;
+        bucket.available -= bytes as f64;
+        if bucket.available < 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(-bucket.available / rate)).await;
+        }
+    }
+}
+
+/// Format a byte count with a decimal unit, e.g. `1.25 GB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1_000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1_000.0;
+    let mut unit = 0;
+    while size >= 1_000.0 && unit < UNITS.len() - 1 {
+        size /= 1_000.0;
+        unit += 1;
+    }
+    format!("{size:.2} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn throttle_lets_a_burst_pass_and_delays_beyond_it() {
+        let throttle = Throttle::new(10_000);
+        let start = Instant::now();
+        throttle.consume(10_000).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        throttle.consume(2_000).await;
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[tokio::test]
+    async fn bytes_are_collected_per_future() {
+        let ((), bytes) = collect(async {
+            record(100);
+            record(20);
+        }).await;
+        assert_eq!(bytes, 120);
+        assert!(total() >= 120);
+    }
+
+    #[test]
+    fn bytes_are_formatted_with_decimal_units() {
+        assert_eq!(format_bytes(999), "999 B");
+        assert_eq!(format_bytes(1_250_000_000), "1.25 GB");
+        assert_eq!(format_bytes(5_000_000_000_000_000), "5000.00 TB");
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use url::Url;
+use defacto::model::ModelSize;
+use defacto::output::OutputFormat;
+
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Configuration file
+    #[arg(long, value_name = "FILE", default_value = "app.toml")]
+    pub config: PathBuf,
+    /// Opencast activity page of a course whose recordings are processed in addition to the
+    /// configured `course_urls`, may be repeated
+    #[arg(long = "course-url", value_name = "URL")]
+    pub course_urls: Vec<Url>,
+    /// File the full results are written to
+    #[arg(long, value_name = "FILE", default_value = "results.csv", conflicts_with = "stdout")]
+    pub output: PathBuf,
+    /// File the results without transcripts are written to
+    #[arg(long, value_name = "FILE", default_value = "results.short.csv")]
+    pub short_output: PathBuf,
+    /// TOTP token for the login instead of prompting for it, required when stdin is not a terminal
+    #[arg(long, value_name = "TOKEN")]
+    pub totp: Option<String>,
+    /// Export an mp3 clip around every match into this directory
+    #[arg(long, value_name = "DIR")]
+    pub export_clips: Option<PathBuf>,
+    /// Seconds of audio to include before and after each exported match
+    #[arg(long, value_name = "SECONDS", default_value_t = 5, requires = "export_clips")]
+    pub clip_padding: u64,
+    /// Write a WebVTT caption file for every video transcribed with whisper into this directory
+    #[arg(long, value_name = "DIR")]
+    pub export_vtt: Option<PathBuf>,
+    /// Additionally transcribe captioned videos with whisper and write a word diff of both
+    /// transcripts for every video into this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "captions_only")]
+    pub compare: Option<PathBuf>,
+    /// Formats of the full results, json writes them with the complete transcripts to results.json
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub format: OutputFormat,
+    /// Indent the JSON results for reading
+    #[arg(long)]
+    pub pretty: bool,
+    /// Write the full results to stdout instead of results.csv or results.json, e.g. to pipe them
+    /// into another tool
+    #[arg(long, conflicts_with_all = ["max_csv_size", "max_csv_rows"])]
+    pub stdout: bool,
+    /// Skip the videos already in results.csv from an earlier run and append the new rows to it
+    #[arg(long, conflicts_with_all = ["stdout", "max_csv_size", "max_csv_rows"])]
+    pub resume: bool,
+    /// Start a new results.partNNN.csv once the current part exceeds this size (e.g. 500MB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_csv_size: Option<u64>,
+    /// Cut transcripts in results.csv to this many characters, matches are still counted on the full text
+    #[arg(long, value_name = "CHARS")]
+    pub max_transcript_chars: Option<usize>,
+    /// Start a new results.partNNN.csv once the current part holds this many rows
+    #[arg(long, value_name = "ROWS")]
+    pub max_csv_rows: Option<usize>,
+    /// Fetch and transcribe every video again instead of reusing the transcripts of earlier runs
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Transcribe in chunks and keep the progress, continuing interrupted transcriptions
+    #[arg(long)]
+    pub resume_whisper: bool,
+    /// Read recording lists and video pages from a directory of saved HTML pages or a HAR file
+    /// instead of requesting them, skipping the login
+    #[arg(long, value_name = "PATH")]
+    pub from_snapshot: Option<PathBuf>,
+    /// Additionally count over all transcripts of each course as one document into one row per
+    /// course of results.course.csv
+    #[arg(long)]
+    pub course_totals: bool,
+    /// Additionally store results in this SQLite database, only updating videos whose transcript changed
+    #[arg(long, value_name = "PATH")]
+    pub db: Option<PathBuf>,
+    /// Additionally rank all videos per pattern by match count into results.by-pattern.csv
+    #[arg(long)]
+    pub by_pattern: bool,
+    /// Additionally write every match with its start, end and the caption cue or whisper segment
+    /// it occurred in to results.timestamps.csv. Title, link and matched text come along, to tell
+    /// the videos and matches apart
+    #[arg(long)]
+    pub timestamps: bool,
+    /// Additionally write every match with the words around it to results.contexts.csv, to tell
+    /// real matches from false positives
+    #[arg(long)]
+    pub contexts: bool,
+    /// Additionally write all results including the timed transcript segments to results.json
+    #[arg(long)]
+    pub include_segments: bool,
+    /// Quickly count only videos with captions, listing captionless ones in results.captionless.txt
+    /// for a later whisper pass
+    #[arg(long)]
+    pub captions_only: bool,
+    /// Only process the videos listed in this file by a --captions-only pass, e.g. to run the slow
+    /// whisper transcriptions overnight. Use --db in both passes to merge their results
+    #[arg(long, value_name = "FILE", conflicts_with = "captions_only")]
+    pub only_captionless: Option<PathBuf>,
+    /// Additionally count matches in the OCR text of slide videos
+    #[cfg(feature = "ocr")]
+    #[arg(long)]
+    pub slide_ocr: bool,
+    /// Transcode downloaded videos to a small mp3 of their audio and delete them before transcribing
+    #[arg(long)]
+    pub transcode_first: bool,
+    /// Skip videos larger than this size (e.g. 2GB), also when they would be streamed
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_video_size: Option<u64>,
+}
+
+/// Parse a byte size with an optional decimal `K`, `M` or `G` suffix, e.g. `2GB` or `500M`
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim().to_ascii_uppercase();
+    let size = size.strip_suffix('B').unwrap_or(&size);
+    let (number, multiplier) = match size.chars().last() {
+        Some('K') => (&size[..size.len() - 1], 1_000),
+        Some('M') => (&size[..size.len() - 1], 1_000_000),
+        Some('G') => (&size[..size.len() - 1], 1_000_000_000),
+        _ => (size, 1),
+    };
+    number.trim()
+        .parse::<u64>()
+        .map(|number| number * multiplier)
+        .map_err(|err| format!("invalid size: {err}"))
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Print the transcript of a single recording to stdout
+    PrintTranscript {
+        /// Url of the video playback page
+        video_url: String,
+        #[command(flatten)]
+        source: TranscriptSource,
+    },
+    /// Fetch all video configs and estimate how long a full run would take
+    Estimate,
+    /// Count the configured patterns again in the transcripts cached by earlier runs and write
+    /// fresh results, without logging in, downloading or transcribing anything
+    Recount,
+    /// Prompt for the login password and store it in the OS keyring, so it does not have to be
+    /// in the config file
+    StorePassword,
+    /// Download a whisper model into the cache and print how to use it
+    DownloadModel {
+        #[arg(value_enum)]
+        size: ModelSize,
+    },
+    /// Run the configured patterns against a text file and print every match in context
+    TestPatterns {
+        /// Text file to search
+        file: PathBuf,
+        /// Words of context to print around each match, defaults to match_context_words
+        #[arg(long, value_name = "WORDS")]
+        context: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Args)]
+#[group(multiple = false)]
+pub struct TranscriptSource {
+    /// Only use the published captions
+    #[arg(long)]
+    pub captions_only: bool,
+    /// Always transcribe the audio with whisper, ignoring captions
+    #[arg(long)]
+    pub force_whisper: bool,
+}
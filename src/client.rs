@@ -1,74 +1,269 @@
 use anyhow::{anyhow, Context};
 use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
-use reqwest::Url;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::{StatusCode, Url};
 use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
 use reqwest_middleware::ClientWithMiddleware;
+use reqwest_scraper::css_selector::Html;
 use reqwest_scraper::ScraperResponse;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, Write};
 use std::ops::Deref;
-use std::path::PathBuf;
-use std::sync::{Arc, LazyLock};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Number of most recent login steps included in login errors
+const REPORTED_LOGIN_STEPS: usize = 5;
+
+/// Name of the hidden input carrying the SimpleSAMLphp login state
+const AUTH_STATE_INPUT_NAME: &str = "AuthState";
+
+/// How often credentials are submitted when the login state expires in between
+const LOGIN_ATTEMPTS: usize = 2;
+
+/// Titles of the SimpleSAMLphp error page for a lost or expired `AuthState`
+const STATE_EXPIRED_TITLES: [&str; 2] = ["State information lost", "Zustandsinformation verloren"];
+
+/// Redirects followed per request before giving up, well above the length of the SAML login chain
+const MAX_REDIRECTS: usize = 15;
+
+/// Headers set explicitly on the credential submission in [`Session::login`]
+const LOGIN_HEADERS: [&str; 6] = ["Origin", "Referer", "Sec-Fetch-Dest", "Sec-Fetch-Mode", "Sec-Fetch-Site", "Sec-Fetch-User"];
+
+/// Convert configured header names and values into a [`HeaderMap`]
+pub fn header_map(headers: &HashMap<String, String>) -> anyhow::Result<HeaderMap> {
+    let mut header_map = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let name = HeaderName::try_from(name)
+            .with_context(|| format!("Invalid header name {name}"))?;
+        let value = HeaderValue::try_from(value)
+            .with_context(|| format!("Invalid value for header {name}"))?;
+        header_map.insert(name, value);
+    }
+    Ok(header_map)
+}
+
+/// Length of a TOTP time window in seconds
+const TOTP_STEP: u64 = 30;
+
+/// TOTP codes generated from a base32 shared secret, the one of the current time window first,
+/// followed by those of the previous and the next window
+pub fn totp_codes(secret: &str) -> anyhow::Result<Vec<String>> {
+    let secret = Secret::Encoded(secret.replace(' ', "").to_uppercase())
+        .to_bytes()
+        .map_err(|err| anyhow!("Invalid TOTP secret: {err:?}"))?;
+    // shorter secrets than the RFC recommends are still in use
+    let totp = TOTP::new_unchecked(Algorithm::SHA1, 6, 1, TOTP_STEP, secret);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(vec![totp.generate(now), totp.generate(now - TOTP_STEP), totp.generate(now + TOTP_STEP)])
+}
+
+/// Value of the `Origin` header of a request sent from a page at `url`: scheme://host, with the
+/// port only if it is not the scheme's default
+fn origin_header(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+/// A request was redirected more than [`MAX_REDIRECTS`] times, e.g. because the session cookie is
+/// rejected during login
+#[derive(Debug, Clone)]
+struct RedirectLoop {
+    url: Url,
+}
+
+impl Display for RedirectLoop {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Redirect loop detected, gave up after {MAX_REDIRECTS} redirects at {}", self.url)
+    }
+}
+
+impl std::error::Error for RedirectLoop {}
+
+/// Words in the error message of the login page that tell the one-time code was rejected rather
+/// than the username or password
+const OTP_REJECTION_WORDS: [&str; 3] = ["otp", "token", "code"];
+
+/// The login page was shown again with an error message, e.g. for wrong credentials
+#[derive(Debug, Clone)]
+struct LoginRejected {
+    message: String,
+}
+
+impl LoginRejected {
+    /// Whether the one-time code was rejected, which a code of an adjacent time window may fix
+    fn rejects_otp(&self) -> bool {
+        let message = self.message.to_lowercase();
+        OTP_REJECTION_WORDS.iter().any(|word| message.contains(word))
+    }
+}
+
+impl Display for LoginRejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
 
-const BASE_URL: LazyLock<Url> = LazyLock::new(|| "https://tuwel.tuwien.ac.at/".parse().unwrap());
+impl std::error::Error for LoginRejected {}
+
+/// Follow redirects up to [`MAX_REDIRECTS`], failing with a [`RedirectLoop`] beyond that
+fn redirect_policy(attempt: Attempt) -> reqwest::redirect::Action {
+    if attempt.previous().len() < MAX_REDIRECTS {
+        return attempt.follow();
+    }
+    let previous = attempt.previous().iter()
+        .map(Url::as_str)
+        .collect::<Vec<_>>()
+        .join(" -> ");
+    tracing::warn!("Stopping redirect loop: {previous}");
+    let url = attempt.url().clone();
+    attempt.error(RedirectLoop { url })
+}
+
+/// A request made during login, reported when the login flow ends up somewhere unexpected
+#[derive(Debug, Clone)]
+struct LoginStep {
+    method: &'static str,
+    /// Url the request landed on after following redirects
+    url: Url,
+    status: StatusCode,
+    title: Option<String>,
+}
+
+impl LoginStep {
+    fn new(method: &'static str, url: Url, status: StatusCode, html: Option<&Html>) -> Self {
+        let title = html
+            .and_then(|html| html.select("title").ok())
+            .and_then(|title| title.first().map(|title| title.text().trim().to_string()));
+        Self { method, url, status, title }
+    }
+
+    fn record(self, steps: &mut Vec<Self>) {
+        tracing::debug!(method = self.method, url = %self.url, status = %self.status, title = self.title, "Login step");
+        steps.push(self);
+    }
+}
+
+impl Display for LoginStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {}", self.method, self.url, self.status)?;
+        if let Some(title) = &self.title {
+            write!(f, " \"{title}\"")?;
+        }
+        Ok(())
+    }
+}
+
+/// Time limits of the requests of a session
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Limit of a whole request, from connecting until the body is read
+    pub request: Duration,
+    pub connect: Duration,
+}
 
 #[derive(Debug)]
 pub enum SessionBuilder {
     New(Option<PathBuf>),
     Restore(File, Option<PathBuf>),
+    /// A session that never logs in, for publicly accessible resources only
+    Anonymous(Option<PathBuf>),
 }
 
 impl SessionBuilder {
-    pub async fn build(self, login_data: &LoginData) -> anyhow::Result<Session> {
+    pub async fn build(self, base_url: Url, login_data: &LoginData, default_headers: HeaderMap, timeouts: Timeouts, check_path: &str) -> anyhow::Result<Session> {
         match self {
             Self::New(cache_path) => {
-                let mut session = Session::new(cache_path);
+                let mut session = Session::new(base_url, cache_path, default_headers, timeouts);
                 session.login(&login_data).await?;
                 Ok(session)
             }
-            Self::Restore(file, cache_path) => {
-                Ok(Session::restore(&file, login_data, cache_path).await?)
-            }
+            Self::Restore(file, cache_path) => match Session::load_cookie_jar(&file) {
+                Ok(cookie_jar) => Ok(Session::restore(cookie_jar, base_url, login_data, cache_path, default_headers, timeouts, check_path).await?),
+                Err(err) => {
+                    tracing::warn!("Logging in again, the saved session could not be restored: {err:#}");
+                    let mut session = Session::new(base_url, cache_path, default_headers, timeouts);
+                    session.login(login_data).await?;
+                    Ok(session)
+                }
+            },
+            Self::Anonymous(cache_path) => Ok(Session::new(base_url, cache_path, default_headers, timeouts)),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct TUWElClientBuilder {
+    /// Root of the Moodle instance, ending in a slash
+    pub base_url: Url,
     pub login_data: LoginData,
     pub session: SessionBuilder,
+    /// Headers sent with every request
+    pub default_headers: HeaderMap,
+    pub timeouts: Timeouts,
+    /// Path a restored session has to be able to open without being redirected to the login
+    pub session_check_path: String,
 }
 
 impl TUWElClientBuilder {
     pub async fn build(self) -> anyhow::Result<TUWElClient> {
-        let session = self.session.build(&self.login_data).await?;
+        let session = self.session.build(self.base_url, &self.login_data, self.default_headers, self.timeouts, &self.session_check_path).await?;
         Ok(TUWElClient {
             session
         })
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LoginData {
     pub username: String,
     pub password: String,
     pub totp: String,
+    /// Codes of the adjacent time windows, tried in order if `totp` is rejected to tolerate a
+    /// skewed clock
+    pub skewed_totps: Vec<String>,
+}
+
+impl Debug for LoginData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginData")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("totp", &"<redacted>")
+            .finish_non_exhaustive()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Session {
+    /// Root of the Moodle instance, the login and session check pages are relative to it
+    base_url: Url,
     client: ClientWithMiddleware,
     cookie_jar: Arc<CookieStoreMutex>,
     session_key: Option<String>,
 }
 
 impl Session {
-    fn build_client(cache_path: Option<PathBuf>, cookie_jar: Arc<CookieStoreMutex>) -> ClientWithMiddleware {
+    fn build_client(cache_path: Option<PathBuf>, cookie_jar: Arc<CookieStoreMutex>, default_headers: HeaderMap, timeouts: Timeouts) -> ClientWithMiddleware {
+        for name in LOGIN_HEADERS {
+            if default_headers.contains_key(name) {
+                tracing::warn!("Default header {name} is overridden during login");
+            }
+        }
+
         let client = reqwest::ClientBuilder::new()
+            .default_headers(default_headers)
             .cookie_store(true)
             .cookie_provider(cookie_jar)
+            .redirect(Policy::custom(redirect_policy))
+            // a hung connection would otherwise block its task forever
+            .timeout(timeouts.request)
+            .connect_timeout(timeouts.connect)
             .build().unwrap();
         
         let manager = cache_path
@@ -83,30 +278,38 @@ impl Session {
             .build()
     }
     
-    pub fn new(cache_path: Option<PathBuf>) -> Self {
+    pub fn new(base_url: Url, cache_path: Option<PathBuf>, default_headers: HeaderMap, timeouts: Timeouts) -> Self {
         let cookie_jar = Arc::new(CookieStoreMutex::new(CookieStore::default()));
-        let client = Self::build_client(cache_path, cookie_jar.clone());
+        let client = Self::build_client(cache_path, cookie_jar.clone(), default_headers, timeouts);
 
         Self {
+            base_url,
             client,
             cookie_jar,
             session_key: None,
         }
     }
     
-    pub async fn restore(file: &File, login_data: &LoginData, cache_path: Option<PathBuf>) -> anyhow::Result<Self> {
-        let cookie_jar = CookieStore::load_json(BufReader::new(file)).unwrap(); // TODO: fix conversion to anyhow::Result
+    /// Read the cookies of a persisted session
+    pub fn load_cookie_jar(file: &File) -> anyhow::Result<CookieStore> {
+        CookieStore::load_json(BufReader::new(file))
+            .map_err(|err| anyhow!(err))
+            .context("Failed to read session cookies")
+    }
+
+    pub async fn restore(cookie_jar: CookieStore, base_url: Url, login_data: &LoginData, cache_path: Option<PathBuf>, default_headers: HeaderMap, timeouts: Timeouts, check_path: &str) -> anyhow::Result<Self> {
         let cookie_jar = Arc::new(CookieStoreMutex::new(cookie_jar));
 
-        let client = Self::build_client(cache_path, cookie_jar.clone());
+        let client = Self::build_client(cache_path, cookie_jar.clone(), default_headers, timeouts);
 
         let mut session = Self {
+            base_url,
             client,
             cookie_jar,
             session_key: None,
         };
 
-        if !session.check().await? {
+        if !session.check(check_path).await? {
             session.login(login_data).await?;
         } else {
             session.load_key().await?;
@@ -115,29 +318,55 @@ impl Session {
         Ok(session)
     }
 
-    pub async fn persist(&self, file: &File) -> anyhow::Result<()> {
-        let cookie_jar = self.cookie_jar.lock().unwrap();
-        cookie_jar.save_incl_expired_and_nonpersistent_json(&mut BufWriter::new(file)).unwrap(); // TODO: complain
-        Ok(())
+    /// Save the session cookies to `path`
+    ///
+    /// They are written to a temporary file next to it that then replaces `path`, so an
+    /// interrupted write never leaves a truncated session behind.
+    pub async fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        let mut cookies = vec![];
+        self.cookie_jar.lock()
+            .map_err(|_| anyhow!("Session cookies were poisoned by a panic"))?
+            .save_incl_expired_and_nonpersistent_json(&mut cookies)
+            .map_err(|err| anyhow!(err))
+            .context("Failed to serialize session cookies")?;
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        file.write_all(&cookies)
+            .and_then(|()| file.sync_all())
+            .context("Failed to write session cookies")?;
+        std::fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))
     }
 
-    pub async fn check(&mut self) -> anyhow::Result<bool> {
-        let home_url = BASE_URL.join("/my/").unwrap();
-        let response = self.client.get(home_url.clone())
-            .send().await.context("Failed to send request to home page")?
-            .error_for_status().context("Failed to send request to home page")?;
-
-        Ok(*response.url() == home_url)
+    /// Whether the session is still logged in, i.e. `check_path` opens without a redirect elsewhere
+    ///
+    /// `check_path` is relative to the base url. Redirects below it (e.g. `my/` to
+    /// `my/courses.php`) and added query parameters still count as logged in.
+    pub async fn check(&mut self, check_path: &str) -> anyhow::Result<bool> {
+        let check_url = self.base_url.join(check_path)?;
+        let response = self.client.get(check_url.clone())
+            .send().await.context("Failed to send request to session check page")?
+            .error_for_status().context("Failed to send request to session check page")?;
+
+        let landed = response.url();
+        tracing::debug!("Session check landed on {landed}");
+        Ok(landed.origin() == check_url.origin() && landed.path().starts_with(check_url.path()))
     }
 
-    async fn login(&mut self, login_data: &LoginData) -> anyhow::Result<()> {
-        let LoginData { username, password, totp } = login_data;
-        let url = BASE_URL.join("/auth/saml2/login.php")?;
+    /// Fetch a fresh SAML login page and post the credentials together with its `AuthState`
+    async fn submit_credentials(&self, login_data: &LoginData, steps: &mut Vec<LoginStep>) -> anyhow::Result<Html> {
+        let LoginData { username, password, totp, .. } = login_data;
+        let url = self.base_url.join("auth/saml2/login.php")?;
         let response = self.client.get(url).send().await?;
         let full_url = response.url().clone();
+        let status = response.status();
 
         let html = response.css_selector().await?;
-        const AUTH_STATE_INPUT_NAME: &str = "AuthState";
+        LoginStep::new("GET", full_url.clone(), status, Some(&html)).record(steps);
         let auth_state_input = html.select(&format!("form[name=f] input[name={AUTH_STATE_INPUT_NAME}]"))?;
         let auth_state_input = auth_state_input
             .first().ok_or(anyhow!("auth state input not found on TU simple saml login page"))?;
@@ -153,9 +382,8 @@ impl Session {
 
         let mut request_url = full_url.clone();
         request_url.set_query(None);
-        let origin = format!("{}://{}", full_url.host().unwrap(), full_url.scheme());
         let request = self.client.post(request_url)
-            .header("Origin", origin)
+            .header("Origin", origin_header(&full_url))
             .header("Referer", full_url.to_string())
             .header("Sec-Fetch-Dest", "document")
             .header("Sec-Fetch-Mode", "navigate")
@@ -163,7 +391,72 @@ impl Session {
             .header("Sec-Fetch-User", "?1")
             .form(&params);
         let response = request.send().await?;
+        let (url, status) = (response.url().clone(), response.status());
         let html = response.css_selector().await?;
+        LoginStep::new("POST", url, status, Some(&html)).record(steps);
+        Ok(html)
+    }
+
+    /// Whether the SAML server rejected the credentials because their `AuthState` expired
+    fn is_state_expired(html: &Html) -> anyhow::Result<bool> {
+        let title = html.select("title")?
+            .first()
+            .map(|title| title.text())
+            .unwrap_or_default();
+        if STATE_EXPIRED_TITLES.contains(&title.trim()) {
+            return Ok(true);
+        }
+        Ok(html.select("body")?
+            .first()
+            .is_some_and(|body| body.text().contains("NOSTATE")))
+    }
+
+    async fn login(&mut self, login_data: &LoginData) -> anyhow::Result<()> {
+        let mut result = self.login_once(login_data).await;
+        for totp in &login_data.skewed_totps {
+            let Err(err) = &result else {
+                break;
+            };
+            if !err.downcast_ref::<LoginRejected>().is_some_and(LoginRejected::rejects_otp) {
+                break;
+            }
+            tracing::warn!("TOTP code rejected, retrying with the code of an adjacent time window: {err:#}");
+            let login_data = LoginData {
+                totp: totp.clone(),
+                ..login_data.clone()
+            };
+            result = self.login_once(&login_data).await;
+        }
+        result
+    }
+
+    async fn login_once(&mut self, login_data: &LoginData) -> anyhow::Result<()> {
+        let mut steps = vec![];
+        let result = self.follow_login(login_data, &mut steps).await;
+        if let Some(step) = steps.last() {
+            tracing::debug!("Login landed on {}", step.url);
+        }
+        result.map_err(|err| {
+            let recent_steps = steps[steps.len().saturating_sub(REPORTED_LOGIN_STEPS)..].iter()
+                .map(|step| format!("  {step}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            err.context(format!("Login failed, last steps:\n{recent_steps}"))
+        })
+    }
+
+    /// Walk the SAML redirect and post chain, recording every request in `steps`
+    async fn follow_login(&mut self, login_data: &LoginData, steps: &mut Vec<LoginStep>) -> anyhow::Result<()> {
+        let mut attempts = 1;
+        let html = loop {
+            let html = self.submit_credentials(login_data, steps).await?;
+            if attempts < LOGIN_ATTEMPTS && Self::is_state_expired(&html)? {
+                tracing::warn!("Login state expired before the credentials were submitted, retrying with a fresh one");
+                attempts += 1;
+                continue;
+            }
+            break html;
+        };
         let title = html.select("title")?
             .first().ok_or(anyhow!("Failed to find login form response title"))?
             .text();
@@ -172,7 +465,7 @@ impl Session {
             "TU Wien Login" => {
                 let error_message = html.select(".message-box.error")?;
                 let error_message = error_message.first().ok_or(anyhow!("Failed to find error message in login form response"))?;
-                return Err(anyhow!(error_message.inner_html()));
+                return Err(LoginRejected { message: error_message.inner_html() }.into());
             }
             "Sende Nachricht" => (),
             _ => return Err(anyhow!("Unexpected login form response title {title}"))
@@ -196,24 +489,25 @@ impl Session {
 
         let url = post_form.attr("action")
             .ok_or(anyhow!("Could not extract message action from login form response"))?;
-        let _ = self.client.post(url)
+        let response = self.client.post(url)
             .form(&message_data)
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
+        LoginStep::new("POST", response.url().clone(), response.status(), None).record(steps);
+        response.error_for_status()?;
 
         self.load_key().await
     }
 
     pub async fn load_key(&mut self) -> anyhow::Result<()> {
-        let home_url = BASE_URL.join("/my/").unwrap();
+        let home_url = self.base_url.join("my/")?;
         let response = self.client.get(home_url)
             .send().await.context("Failed to send request to home page")?
             .error_for_status().context("Failed to send request to home page")?;
 
         let xpath = response.xpath().await?;
 
-        let script = xpath.select("/html/head/script[3]").context("Failed to find session config script")?
+        let script = xpath.select("//script[contains(text(), 'M.cfg = ')]").context("Failed to find session config script")?
             .as_node().ok_or(anyhow!("Failed to find moodle config script"))?;
         let moodle_config = script.text();
         let (moodle_config, _) = moodle_config
@@ -237,8 +531,8 @@ pub struct TUWElClient {
 }
 
 impl TUWElClient {
-    pub async fn persist(&self, file: &File) -> anyhow::Result<()> {
-        self.session.persist(file).await
+    pub async fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        self.session.persist(path).await
     }
 }
 
@@ -312,3 +606,74 @@ impl Deref for TUWElClient {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+
+    #[test]
+    fn origin_header_has_no_path_or_default_port() {
+        let url = Url::parse("https://idp.zid.tuwien.ac.at:443/simplesaml/module.php/core/loginuserpass.php?AuthState=x").unwrap();
+        assert_eq!(origin_header(&url), "https://idp.zid.tuwien.ac.at");
+        let url = Url::parse("http://localhost:8080/login.php").unwrap();
+        assert_eq!(origin_header(&url), "http://localhost:8080");
+    }
+
+    #[test]
+    fn only_otp_rejections_are_retried() {
+        let rejection = |message: &str| LoginRejected { message: message.to_string() };
+        assert!(rejection("Ungültiger OTP Code").rejects_otp());
+        assert!(rejection("Invalid token").rejects_otp());
+        assert!(!rejection("Benutzername oder Passwort falsch").rejects_otp());
+    }
+
+    #[test]
+    fn rejection_is_found_behind_context() {
+        let err = anyhow::Error::from(LoginRejected { message: "Invalid token".to_string() })
+            .context("Login failed, last steps:");
+        assert!(err.downcast_ref::<LoginRejected>().is_some_and(LoginRejected::rejects_otp));
+    }
+
+    #[test]
+    fn unreadable_session_cookies_are_an_error() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"not a cookie jar").unwrap();
+        file.rewind().unwrap();
+        let err = Session::load_cookie_jar(&file).unwrap_err();
+        assert!(format!("{err:#}").starts_with("Failed to read session cookies"));
+    }
+
+    #[tokio::test]
+    async fn persisted_session_replaces_the_old_file() {
+        let base_url = Url::parse("https://tuwel.example/").unwrap();
+        let timeouts = Timeouts { request: Duration::from_secs(1), connect: Duration::from_secs(1) };
+        let session = Session::new(base_url.clone(), None, HeaderMap::new(), timeouts);
+        session.cookie_jar.lock().unwrap().parse("MoodleSession=abc; Max-Age=3600", &base_url).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        std::fs::write(&path, "stale").unwrap();
+        session.persist(&path).await.unwrap();
+
+        let cookie_jar = Session::load_cookie_jar(&File::open(&path).unwrap()).unwrap();
+        assert_eq!(cookie_jar.get("tuwel.example", "/", "MoodleSession").map(|cookie| cookie.value()), Some("abc"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_persist_leaves_the_old_session() {
+        let base_url = Url::parse("https://tuwel.example/").unwrap();
+        let timeouts = Timeouts { request: Duration::from_secs(1), connect: Duration::from_secs(1) };
+        let session = Session::new(base_url, None, HeaderMap::new(), timeouts);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".session.json");
+        std::fs::write(&path, "previous session").unwrap();
+        // the temporary file cannot be created where a directory is
+        std::fs::create_dir(dir.path().join(".session.json.tmp")).unwrap();
+
+        assert!(session.persist(&path).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "previous session");
+    }
+}
@@ -0,0 +1,75 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::anyhow;
+use ffmpeg_next::{codec, encoder, format, frame, ChannelLayout, Packet, Rational};
+use ffmpeg_next::format::{sample, Sample};
+
+#[derive(Debug, Clone)]
+pub struct ClipExport {
+    pub dir: PathBuf,
+    /// Audio included before and after each match
+    pub padding: Duration,
+}
+
+/// Turn a title into a lowercase, file name safe slug
+pub fn slugify(title: &str) -> String {
+    title.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Encode the given span of mono f32 audio into an mp3 file
+pub fn export_clip(audio: &[f32], rate: u32, span_ms: Range<u64>, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let to_index = |ms: u64| ((ms * rate as u64 / 1000) as usize).min(audio.len());
+    let samples = &audio[to_index(span_ms.start)..to_index(span_ms.end)];
+
+    let codec = encoder::find(codec::Id::MP3)
+        .ok_or(anyhow!("No mp3 encoder available in ffmpeg"))?;
+    let time_base = Rational::new(1, rate as i32);
+
+    let mut octx = format::output(&path)?;
+    let mut stream = octx.add_stream(codec)?;
+    let mut encoder = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .audio()?;
+    encoder.set_rate(rate as i32);
+    encoder.set_channel_layout(ChannelLayout::MONO);
+    encoder.set_format(Sample::F32(sample::Type::Planar));
+    encoder.set_bit_rate(64_000);
+    encoder.set_time_base(time_base);
+    let mut encoder = encoder.open_as(codec)?;
+    stream.set_parameters(&encoder);
+
+    octx.write_header()?;
+    let stream_time_base = octx.stream(0)
+        .ok_or(anyhow!("Clip output stream vanished"))?
+        .time_base();
+
+    let write_packets = |encoder: &mut encoder::audio::Encoder, octx: &mut format::context::Output| -> anyhow::Result<()> {
+        let mut packet = Packet::empty();
+        while encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(0);
+            packet.rescale_ts(time_base, stream_time_base);
+            packet.write_interleaved(octx)?;
+        }
+        Ok(())
+    };
+
+    let frame_size = (encoder.frame_size() as usize).max(1);
+    for (index, chunk) in samples.chunks(frame_size).enumerate() {
+        let mut frame = frame::Audio::new(Sample::F32(sample::Type::Planar), chunk.len(), ChannelLayout::MONO);
+        frame.set_rate(rate);
+        frame.plane_mut::<f32>(0).copy_from_slice(chunk);
+        frame.set_pts(Some((index * frame_size) as i64));
+        encoder.send_frame(&frame)?;
+        write_packets(&mut encoder, &mut octx)?;
+    }
+    encoder.send_eof()?;
+    write_packets(&mut encoder, &mut octx)?;
+
+    octx.write_trailer()?;
+    Ok(())
+}
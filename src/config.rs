@@ -1,27 +1,600 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
 use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use url::Url;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Service the password is stored under in the OS keyring, with the username as the account
+pub const KEYRING_SERVICE: &str = "defacto";
+
+/// Login credentials, each overridden by the environment variable in [`Config::read`]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct LoginData {
+    /// Overridden by `DEFACTO_USERNAME`
+    #[serde(default)]
     pub username: String,
+    /// Overridden by `DEFACTO_PASSWORD`, read from the OS keyring if neither is set
+    #[serde(default)]
     pub password: String,
+    /// Base32 TOTP shared secret, generates the TOTP codes instead of prompting for them,
+    /// overridden by `DEFACTO_TOTP_SECRET`
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+}
+
+impl LoginData {
+    /// Override the credentials with the `DEFACTO_*` variables `lookup` finds
+    pub fn override_with(&mut self, lookup: impl Fn(&str) -> Option<String>) {
+        if let Some(username) = lookup("DEFACTO_USERNAME") {
+            self.username = username;
+        }
+        if let Some(password) = lookup("DEFACTO_PASSWORD") {
+            self.password = password;
+        }
+        if let Some(totp_secret) = lookup("DEFACTO_TOTP_SECRET") {
+            self.totp_secret = Some(totp_secret);
+        }
+    }
+
+    /// Read a missing password from the OS keyring, failing if the credentials are still
+    /// incomplete after that
+    pub fn require(&mut self) -> anyhow::Result<()> {
+        if self.password.is_empty() && !self.username.is_empty() {
+            match keyring_entry(&self.username).and_then(|entry| entry.get_password()) {
+                Ok(password) => self.password = password,
+                Err(keyring::Error::NoEntry) => (),
+                Err(err) => tracing::warn!("Failed to read the password from the OS keyring: {err}"),
+            }
+        }
+        if self.username.is_empty() || self.password.is_empty() {
+            return Err(anyhow!("No login credentials, set username and password in [login] or DEFACTO_USERNAME and DEFACTO_PASSWORD, or store the password with store-password"));
+        }
+        Ok(())
+    }
+}
+
+impl Debug for LoginData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginData")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("totp_secret", &self.totp_secret.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// The keyring entry of the password of `username`
+pub fn keyring_entry(username: &str) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, username)
 }
 
 fn default_cache_path() -> PathBuf {
     ".cache".into()
 }
 
+fn default_base_url() -> Url {
+    "https://tuwel.tuwien.ac.at/".parse().unwrap()
+}
+
+fn default_session_check_path() -> String {
+    "my/".to_string()
+}
+
+fn default_discovery_concurrency() -> usize {
+    8
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_whisper_concurrency() -> usize {
+    1
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_request_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_download_timeout_seconds() -> u64 {
+    2 * 60 * 60
+}
+
+fn default_whisper_server_model() -> String {
+    "whisper-1".to_string()
+}
+
+/// An OpenAI compatible transcription endpoint used instead of the local whisper model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhisperServer {
+    /// Full endpoint url, e.g. `https://api.openai.com/v1/audio/transcriptions`
+    pub url: Url,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default = "default_whisper_server_model")]
+    pub model: String,
+}
+
+/// What to do when a video has no usable captions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackPolicy {
+    /// Always transcribe the audio with whisper
+    #[default]
+    Always,
+    /// Fail the video instead of transcribing it
+    Never,
+    /// Ask on stdin for every video
+    AskPerVideo,
+}
+
+/// Which source of the main audio stream is downloaded for whisper
+///
+/// Only the `mp4` and `audio` lists of the stream's `sources` are inspected, as the manifest states
+/// no audio bitrates, the resolution stands in for the audio quality.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioStreamPreference {
+    /// The mp4 with the lowest resolution, the smallest download
+    #[default]
+    Smallest,
+    /// The mp4 with the highest resolution, whose audio track is the least likely to be downmixed
+    Largest,
+    /// An audio only source if there is one, the mp4 with the highest resolution otherwise
+    BestAudio,
+}
+
+/// How match timestamps are written to results.timestamps.csv
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// Whole milliseconds, e.g. `3723456`
+    Milliseconds,
+    /// `hh:mm:ss.mmm`, e.g. `01:02:03.456`
+    #[default]
+    Clock,
+    /// Seconds with millisecond decimals, e.g. `3723.456`
+    Seconds,
+}
+
+/// Decoding parameters of the local whisper model, trading speed for robustness on noisy audio
+///
+/// When a decode fails the entropy or log probability thresholds, whisper retries it with the
+/// temperature raised by `temperature_inc`, so more passes are made for difficult audio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhisperDecoding {
+    /// Spoken language of the recordings, `auto` detects it per recording, defaults to the first
+    /// of `caption_languages`
+    pub language: Option<String>,
+    /// Initial sampling temperature, 0 decodes greedily
+    pub temperature: f32,
+    /// Temperature increase per fallback pass, 0 disables the fallback
+    pub temperature_inc: f32,
+    /// Number of candidates sampled per pass at a non-zero temperature
+    pub best_of: i32,
+    /// Compression ratio above which a decode is considered failed
+    pub entropy_threshold: f32,
+    /// Average log probability below which a decode is considered failed
+    pub logprob_threshold: f32,
+}
+
+impl Default for WhisperDecoding {
+    fn default() -> Self {
+        Self {
+            language: None,
+            temperature: 0.0,
+            temperature_inc: 0.2,
+            best_of: 1,
+            entropy_threshold: 2.4,
+            logprob_threshold: -1.0,
+        }
+    }
+}
+
+/// How the audio transcribed by two consecutive whisper chunks is resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeamStrategy {
+    /// Cut both chunks at the middle of their overlap
+    #[default]
+    Midpoint,
+    /// Splice at the longest common word sequence of both chunks within the overlap, falling back
+    /// to the midpoint if they share no words
+    Align,
+}
+
+/// Splitting of whisper transcriptions into chunks and the overlap between them, so words spoken
+/// across a chunk boundary are not cut off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhisperChunking {
+    /// Seconds of audio per chunk, e.g. 30, unset transcribes in a single pass unless resuming
+    /// with `--resume-whisper`, which then uses 300
+    pub chunk_seconds: Option<u32>,
+    /// Seconds of audio each chunk extends into the next one, 0 disables the overlap
+    pub overlap_seconds: u32,
+    pub seam: SeamStrategy,
+}
+
+/// Subdirectories of the cache, relative to `cache_path` unless absolute
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheLayout {
+    pub sessions: PathBuf,
+    pub videos: PathBuf,
+    pub transcripts: PathBuf,
+    pub audio: PathBuf,
+    pub models: PathBuf,
+}
+
+impl Default for CacheLayout {
+    fn default() -> Self {
+        Self {
+            sessions: "sessions".into(),
+            videos: "videos".into(),
+            transcripts: "transcripts".into(),
+            audio: "audio".into(),
+            models: "models".into(),
+        }
+    }
+}
+
+fn default_case_insensitive() -> bool {
+    true
+}
+
+fn default_whole_word() -> bool {
+    true
+}
+
+/// A regex counted in every transcript
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternConfig {
+    /// Column name of the pattern count
+    pub name: String,
+    pub regex: String,
+    #[serde(default = "default_case_insensitive")]
+    pub case_insensitive: bool,
+    /// Require word boundaries around the match, so the pattern does not match inside longer
+    /// words
+    #[serde(default = "default_whole_word")]
+    pub whole_word: bool,
+    /// Require a lowercase letter and whitespace before the match, so the pattern only counts
+    /// within a sentence and not at the start of a capitalized name or title
+    #[serde(default)]
+    pub after_lowercase: bool,
+    /// Match against the transcript with every run of whitespace replaced by a single space
+    #[serde(default)]
+    pub normalize_whitespace: bool,
+    /// Match against the transcript with hyphens between letters removed, e.g. `de-facto` as
+    /// `defacto`
+    #[serde(default)]
+    pub collapse_hyphens: bool,
+}
+
+impl PatternConfig {
+    fn whole_word(name: &str, regex: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            regex: regex.to_string(),
+            case_insensitive: true,
+            whole_word: true,
+            after_lowercase: false,
+            normalize_whitespace: false,
+            collapse_hyphens: false,
+        }
+    }
+}
+
+/// The patterns counted when none are configured
+pub fn default_patterns() -> Vec<PatternConfig> {
+    vec![
+        // whisper writes it as one word or hyphenated as often as two words
+        PatternConfig {
+            normalize_whitespace: true,
+            collapse_hyphens: true,
+            ..PatternConfig::whole_word("De facto", "de ?facto")
+        },
+        PatternConfig::whole_word("trivial", "trivial"),
+        PatternConfig::whole_word("Ergibt das Sinn", "ergibt\\s+das\\s+sinn"),
+    ]
+}
+
+/// Patterns that count only when they occur in order within a time window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sequence {
+    /// Column name of the sequence count
+    pub name: String,
+    /// Names of the patterns in the order they have to occur
+    pub patterns: Vec<String>,
+    /// Maximum seconds from the start of the first to the start of the last pattern
+    pub window_seconds: u64,
+}
+
+fn default_whisper_realtime_factor() -> f64 {
+    1.0
+}
+
+fn default_match_context_words() -> usize {
+    8
+}
+
+fn default_sanitize_csv() -> bool {
+    true
+}
+
+fn default_download_video() -> bool {
+    true
+}
+
+fn default_caption_languages() -> Vec<String> {
+    vec!["de".to_string()]
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    /// Root of the Moodle instance to log into, e.g. `https://moodle.example.org/`
+    #[serde(default = "default_base_url")]
+    pub base_url: Url,
+    #[serde(default)]
     pub login: LoginData,
+    /// Opencast activity pages of the courses to process, in addition to those passed with
+    /// `--course-url`
+    #[serde(default)]
+    pub course_urls: Vec<Url>,
     #[serde(default = "default_cache_path")]
     pub cache_path: PathBuf,
+    #[serde(default)]
+    pub cache_layout: CacheLayout,
+    /// Base url of an Opencast search service (e.g. `https://opencast.example.org/search/`)
+    /// to prefer indexed segment texts over captions and whisper
+    #[serde(default)]
+    pub search_api_url: Option<Url>,
+    /// Caption languages in order of preference
+    #[serde(default = "default_caption_languages")]
+    pub caption_languages: Vec<String>,
+    /// Use captions of any language when none of `caption_languages` are available
+    #[serde(default)]
+    pub allow_any_language: bool,
+    /// Replacements applied to transcripts before matching, e.g. `"defakto" = "de facto"`
+    #[serde(default)]
+    pub replacements: IndexMap<String, String>,
+    /// Episode ids to never process, e.g. lectures that were verified manually. Results stored by
+    /// earlier runs with `--db` are left untouched.
+    #[serde(default)]
+    pub skip_episodes: Vec<String>,
+    /// Patterns counted in every transcript, each becoming a column of the results
+    #[serde(default = "default_patterns")]
+    pub patterns: Vec<PatternConfig>,
+    /// Pattern sequences counted in addition to the single patterns
+    #[serde(default)]
+    pub sequences: Vec<Sequence>,
+    /// Page a restored session must reach to count as logged in, including pages below it,
+    /// relative to `base_url`
+    #[serde(default = "default_session_check_path")]
+    pub session_check_path: String,
+    /// Maximum number of video pages requested at once while collecting the video configs
+    #[serde(default = "default_discovery_concurrency")]
+    pub discovery_concurrency: usize,
+    /// Maximum number of videos processed at once
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Maximum number of videos transcribed with whisper at once, whisper already uses all cores
+    #[serde(default = "default_whisper_concurrency")]
+    pub whisper_concurrency: usize,
+    /// How often a request for pages, captions or videos is sent again after a connection error
+    /// or a server error, with exponentially growing delays in between
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Seconds a request may take in total before it is given up, except for video downloads
+    #[serde(default = "default_request_timeout_seconds")]
+    pub request_timeout_seconds: u64,
+    /// Seconds to wait for a connection to be established
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+    /// Seconds a video download or whisper server transcription may take in total, as long
+    /// recordings legitimately take minutes
+    #[serde(default = "default_download_timeout_seconds")]
+    pub download_timeout_seconds: u64,
+    /// Headers added to every request, e.g. for institutional proxies
+    #[serde(default)]
+    pub default_headers: HashMap<String, String>,
+    /// GGML model file of the local whisper, falls back to the `WHISPER_MODEL` environment variable
+    #[serde(default)]
+    pub whisper_model: Option<PathBuf>,
+    /// Transcribe with a remote whisper server instead of the local model
+    #[serde(default)]
+    pub whisper_server: Option<WhisperServer>,
+    /// Download recordings into the cache before transcribing them with the local whisper model,
+    /// disable to let ffmpeg stream them without writing anything to disk. Streamed recordings are
+    /// read again whenever they are transcribed
+    #[serde(default = "default_download_video")]
+    pub download_video: bool,
+    /// Limit of the combined rate of all video downloads, unlimited if unset. Recordings ffmpeg
+    /// streams itself are not limited
+    #[serde(default)]
+    pub max_download_bytes_per_sec: Option<u64>,
+    /// Which source of a recording is downloaded for whisper
+    #[serde(default)]
+    pub audio_stream_preference: AudioStreamPreference,
+    /// Whether videos without captions fall back to whisper
+    #[serde(default)]
+    pub fallback_policy: FallbackPolicy,
+    /// Regex whose first capture group extracts the episode JSON from a video page script, for
+    /// players that do not assign it to `window.episode`, e.g. `(?s)player\.init\((\{.*\})\);`
+    #[serde(default)]
+    pub video_config_pattern: Option<String>,
+    /// Decoding parameters of the local whisper model
+    #[serde(default)]
+    pub whisper_decoding: WhisperDecoding,
+    /// Chunk length and overlap of whisper transcriptions
+    #[serde(default)]
+    pub whisper_chunking: WhisperChunking,
+    /// Seconds whisper takes per second of audio on this machine, used to estimate run times
+    #[serde(default = "default_whisper_realtime_factor")]
+    pub whisper_realtime_factor: f64,
+    /// Strip stray control characters from transcripts in results.csv, disable for raw output
+    #[serde(default = "default_sanitize_csv")]
+    pub sanitize_csv: bool,
+    /// Format of the match timestamps in results.timestamps.csv
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+    /// Words before and after every match quoted in results.contexts.csv
+    #[serde(default = "default_match_context_words")]
+    pub match_context_words: usize,
 }
 
 impl Config {
+    /// Load the config file, with credentials from the environment taking precedence over it and
+    /// a missing password read from the OS keyring
     pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut data = Self::read(path)?;
+        data.login.require()?;
+        Ok(data)
+    }
+
+    /// Read the config file with the credentials from the environment applied, which may still be
+    /// incomplete
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
         let data = std::fs::read_to_string(path)?;
-        let data = toml::from_str(&data)?;
+        let mut data: Self = toml::from_str(&data)?;
+
+        data.login.override_with(|name| std::env::var(name).ok());
+        // without a trailing slash the last path segment would be replaced by joined paths
+        if !data.base_url.path().ends_with('/') {
+            let path = format!("{}/", data.base_url.path());
+            data.base_url.set_path(&path);
+        }
         Ok(data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use crate::client::{LoginData, SessionBuilder, TUWElClientBuilder, Timeouts};
+    use crate::retry;
+
+    #[test]
+    fn config_without_credentials_is_read() {
+        let mut config: Config = toml::from_str("").unwrap();
+        assert!(config.login.username.is_empty());
+        assert!(config.login.require().is_err());
+        config.login.username = "e01234567".to_string();
+        config.login.password = "secret".to_string();
+        assert!(config.login.require().is_ok());
+    }
+
+    #[test]
+    fn timestamp_format_is_read_in_snake_case() {
+        let config: Config = toml::from_str("timestamp_format = \"milliseconds\"").unwrap();
+        assert_eq!(config.timestamp_format, TimestampFormat::Milliseconds);
+        assert_eq!(toml::from_str::<Config>("").unwrap().timestamp_format, TimestampFormat::Clock);
+    }
+
+    #[test]
+    fn concurrency_limits_default_to_one_transcription_at_a_time() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!((config.discovery_concurrency, config.concurrency, config.whisper_concurrency), (8, 4, 1));
+        let config: Config = toml::from_str("concurrency = 2\nwhisper_concurrency = 3").unwrap();
+        assert_eq!((config.concurrency, config.whisper_concurrency), (2, 3));
+    }
+
+    #[test]
+    fn credentials_from_the_environment_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.toml");
+        std::fs::write(&path, "[login]\nusername = \"file\"\npassword = \"file\"\n").unwrap();
+        let mut config = Config::read(&path).unwrap();
+        config.login.override_with(|name| match name {
+            "DEFACTO_USERNAME" => Some("env".to_string()),
+            "DEFACTO_PASSWORD" => Some("secret".to_string()),
+            "DEFACTO_TOTP_SECRET" => Some("JBSWY3DPEHPK3PXP".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.login.username, "env");
+        assert_eq!(config.login.password, "secret");
+        assert_eq!(config.login.totp_secret.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+    }
+
+    #[test]
+    fn whisper_model_is_optional() {
+        assert_eq!(toml::from_str::<Config>("").unwrap().whisper_model, None);
+        let config: Config = toml::from_str("whisper_model = \".cache/models/ggml-base.bin\"").unwrap();
+        assert_eq!(config.whisper_model, Some(PathBuf::from(".cache/models/ggml-base.bin")));
+    }
+
+    #[test]
+    fn base_url_gets_a_trailing_slash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.toml");
+        std::fs::write(&path, "base_url = \"https://moodle.example.org/tuwel\"").unwrap();
+        let config = Config::read(&path).unwrap();
+        assert_eq!(config.base_url.join("my/").unwrap().as_str(), "https://moodle.example.org/tuwel/my/");
+        assert_eq!(toml::from_str::<Config>("").unwrap().base_url.as_str(), "https://tuwel.tuwien.ac.at/");
+    }
+
+    #[test]
+    fn timeouts_default_when_unset() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.request_timeout_seconds, 30);
+        assert_eq!(config.connect_timeout_seconds, 10);
+        assert_eq!(config.download_timeout_seconds, 2 * 60 * 60);
+    }
+
+    #[test]
+    fn timeouts_are_read_from_the_config() {
+        let config: Config = toml::from_str("
+            request_timeout_seconds = 5
+            connect_timeout_seconds = 2
+            download_timeout_seconds = 600
+        ").unwrap();
+        assert_eq!(config.request_timeout_seconds, 5);
+        assert_eq!(config.connect_timeout_seconds, 2);
+        assert_eq!(config.download_timeout_seconds, 600);
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_time_out_after_the_configured_limit() {
+        let config: Config = toml::from_str("request_timeout_seconds = 1").unwrap();
+        // connections are accepted into the backlog, but never answered
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let client = TUWElClientBuilder {
+            base_url: config.base_url.clone(),
+            login_data: LoginData {
+                username: String::new(),
+                password: String::new(),
+                totp: String::new(),
+                skewed_totps: vec![],
+            },
+            session: SessionBuilder::Anonymous(Some(cache.path().to_path_buf())),
+            default_headers: Default::default(),
+            timeouts: Timeouts {
+                request: Duration::from_secs(config.request_timeout_seconds),
+                connect: Duration::from_secs(config.connect_timeout_seconds),
+            },
+            session_check_path: config.session_check_path.clone(),
+        }
+            .build().await.unwrap();
+
+        let started = Instant::now();
+        let err = client.get(url).send().await.unwrap_err();
+        let elapsed = started.elapsed();
+        assert!(retry::reqwest_error(&err).is_some_and(reqwest::Error::is_timeout), "{err:?}");
+        assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(3), "timed out after {elapsed:?}");
+    }
+}
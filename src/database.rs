@@ -0,0 +1,192 @@
+use std::path::Path;
+use anyhow::Context;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use crate::defacto::DataRow;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS videos (
+    link TEXT PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    course TEXT NOT NULL DEFAULT '',
+    transcript_hash TEXT NOT NULL,
+    fetched_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS counts (
+    link TEXT NOT NULL REFERENCES videos(link),
+    pattern TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (link, pattern)
+);
+CREATE TABLE IF NOT EXISTS versions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    link TEXT NOT NULL REFERENCES videos(link),
+    transcript_hash TEXT NOT NULL,
+    counts TEXT NOT NULL,
+    recorded_at TEXT NOT NULL
+);
+";
+
+/// SQLite store accumulating results across runs
+///
+/// Every video keeps its course and latest counts in `videos`/`counts`. Whenever a video's transcript
+/// or counts change, the new counts are also appended to `versions`, so count movements can be traced.
+pub struct Database {
+    connection: Connection,
+}
+
+impl Database {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let connection = Connection::open(path).context("Failed to open results database")?;
+        connection.execute_batch(SCHEMA).context("Failed to create results database schema")?;
+        Self::migrate(&connection).context("Failed to migrate results database schema")?;
+        Ok(Self { connection })
+    }
+
+    /// Add the columns introduced after a database was created
+    fn migrate(connection: &Connection) -> anyhow::Result<()> {
+        let has_course = connection.prepare("SELECT name FROM pragma_table_info('videos')")?
+            .query_map([], |column| column.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .any(|name| name == "course");
+        if !has_course {
+            connection.execute("ALTER TABLE videos ADD COLUMN course TEXT NOT NULL DEFAULT ''", [])?;
+        }
+        Ok(())
+    }
+
+    pub fn transcript_hash(transcript: &str) -> String {
+        hex::encode(Sha256::digest(transcript.as_bytes()))
+    }
+
+    /// Insert or update a video, returning whether anything changed
+    ///
+    /// Rows whose transcript hash and counts match the stored ones are left untouched. Counts of
+    /// an unchanged transcript still change with the configured patterns, e.g. on `recount`.
+    pub fn upsert(&mut self, row: &DataRow) -> anyhow::Result<bool> {
+        let hash = Self::transcript_hash(row.transcript());
+        let counts = serde_json::to_string(row.counts())?;
+        let stored = self.connection
+            .query_row(
+                "SELECT transcript_hash, (SELECT counts FROM versions WHERE link = ?1 ORDER BY id DESC LIMIT 1)
+                 FROM videos WHERE link = ?1",
+                [row.link()],
+                |stored| Ok((stored.get::<_, String>(0)?, stored.get::<_, Option<String>>(1)?)),
+            )
+            .optional()?;
+        if stored.as_ref().is_some_and(|(stored_hash, stored_counts)| *stored_hash == hash && stored_counts.as_ref() == Some(&counts)) {
+            tracing::debug!("Transcript and counts of {} unchanged", row.link());
+            // rows stored before courses were recorded
+            self.connection.execute(
+                "UPDATE videos SET course = ?2 WHERE link = ?1 AND ?2 != ''",
+                params![row.link(), row.course()],
+            )?;
+            return Ok(false);
+        }
+
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO videos (link, title, course, transcript_hash, fetched_at) VALUES (?1, ?2, ?3, ?4, datetime('now'))
+             ON CONFLICT(link) DO UPDATE SET title = excluded.title, transcript_hash = excluded.transcript_hash, fetched_at = excluded.fetched_at,
+                 course = CASE excluded.course WHEN '' THEN videos.course ELSE excluded.course END",
+            params![row.link(), row.title(), row.course(), hash],
+        )?;
+        transaction.execute("DELETE FROM counts WHERE link = ?1", [row.link()])?;
+        for (pattern, count) in row.counts() {
+            transaction.execute(
+                "INSERT INTO counts (link, pattern, count) VALUES (?1, ?2, ?3)",
+                params![row.link(), pattern, *count as i64],
+            )?;
+        }
+        transaction.execute(
+            "INSERT INTO versions (link, transcript_hash, counts, recorded_at) VALUES (?1, ?2, ?3, datetime('now'))",
+            params![row.link(), hash, counts],
+        )?;
+        transaction.commit()?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(transcript: &str, count: usize) -> DataRow {
+        row_in("", transcript, count)
+    }
+
+    fn row_in(course: &str, transcript: &str, count: usize) -> DataRow {
+        serde_json::from_value(serde_json::json!({
+            "title": "Vorlesung 1",
+            "link": "https://example.org/video?e=1",
+            "course": course,
+            "transcript": transcript,
+            "counts": { "trivial": count },
+            "word_count": 3,
+            "duration_ms": 0,
+            "sequence_counts": {},
+            "matches": [],
+            "warnings": [],
+        })).unwrap()
+    }
+
+    fn versions(database: &Database) -> i64 {
+        database.connection.query_row("SELECT COUNT(*) FROM versions", [], |count| count.get(0)).unwrap()
+    }
+
+    #[test]
+    fn unchanged_row_is_skipped() {
+        let mut database = Database::open(":memory:").unwrap();
+        assert!(database.upsert(&row("das ist trivial", 1)).unwrap());
+        assert!(!database.upsert(&row("das ist trivial", 1)).unwrap());
+        assert_eq!(versions(&database), 1);
+    }
+
+    #[test]
+    fn changed_counts_of_same_transcript_are_stored() {
+        let mut database = Database::open(":memory:").unwrap();
+        database.upsert(&row("das ist trivial", 1)).unwrap();
+        assert!(database.upsert(&row("das ist trivial", 0)).unwrap());
+
+        let count: i64 = database.connection
+            .query_row("SELECT count FROM counts WHERE pattern = 'trivial'", [], |count| count.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+        assert_eq!(versions(&database), 2);
+    }
+
+    fn course(database: &Database) -> String {
+        database.connection.query_row("SELECT course FROM videos", [], |course| course.get(0)).unwrap()
+    }
+
+    #[test]
+    fn course_is_kept_when_a_later_row_has_none() {
+        let mut database = Database::open(":memory:").unwrap();
+        database.upsert(&row_in("Analysis", "das ist trivial", 1)).unwrap();
+        database.upsert(&row("das ist nicht trivial", 1)).unwrap();
+        assert_eq!(course(&database), "Analysis");
+    }
+
+    #[test]
+    fn course_is_filled_in_for_unchanged_rows() {
+        let mut database = Database::open(":memory:").unwrap();
+        database.upsert(&row("das ist trivial", 1)).unwrap();
+        assert!(!database.upsert(&row_in("Analysis", "das ist trivial", 1)).unwrap());
+        assert_eq!(course(&database), "Analysis");
+    }
+
+    #[test]
+    fn databases_without_courses_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.sqlite");
+        Connection::open(&path).unwrap()
+            .execute_batch("CREATE TABLE videos (link TEXT PRIMARY KEY NOT NULL, title TEXT NOT NULL, transcript_hash TEXT NOT NULL, fetched_at TEXT NOT NULL);")
+            .unwrap();
+
+        let mut database = Database::open(&path).unwrap();
+        database.upsert(&row_in("Analysis", "das ist trivial", 1)).unwrap();
+        assert_eq!(course(&database), "Analysis");
+    }
+}
@@ -1,35 +1,667 @@
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use anyhow::{anyhow, Context};
-use ffmpeg_next::{channel_layout, format::input, util::{media::Type, frame::Audio}};
+use futures::stream;
+use ffmpeg_next::{channel_layout::ChannelLayout, format::input, util::{media::Type, frame::Audio}};
+use csv::StringRecord;
 use ffmpeg_next::format::{sample, Sample};
+use indexmap::IndexMap;
 use json::JsonValue;
-use regex::{Regex, RegexBuilder};
-use reqwest::IntoUrl;
-use reqwest_scraper::ScraperResponse;
+use regex::{Regex, RegexSet, SetMatches};
+use reqwest::{IntoUrl, Response, StatusCode, Url};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::multipart::{Form, Part};
+use reqwest_scraper::xpath::XHtml;
 use serde::{Deserialize, Serialize};
+use serde::de::IgnoredAny;
+use sha2::{Digest, Sha256};
+use subtp::srt::SubRip;
 use subtp::vtt::{VttBlock, WebVtt};
-use tokio::task;
-use tracing::{span, Instrument, Level};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::{self, JoinSet};
+use tracing::{field, span, Instrument, Level, Span};
 use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+use crate::bandwidth;
 use crate::client::TUWElClient;
+use crate::clip::{self, ClipExport};
+use crate::config::{self, AudioStreamPreference, CacheLayout, PatternConfig, FallbackPolicy, Sequence, TimestampFormat, WhisperChunking, WhisperDecoding, WhisperServer};
+use crate::episode::{Attachment, Caption, EpisodeConfig, Stream};
+#[cfg(feature = "ocr")]
+use crate::ocr;
+use crate::output;
+use crate::postprocess::{self, PostProcessor};
+use crate::retry;
+use crate::seam;
+use crate::snapshot::Snapshot;
+use crate::transcript::{self, Match, Segment, Transcript, TranscriptSource};
+use crate::warnings::{self, data_warning};
 
-const PATTERNS: [(&'static str, LazyLock<Regex>); 3] = [
-    ("De facto", LazyLock::new(|| RegexBuilder::new("[^a-zA-Z]de\\s+facto[^a-zA-Z]").case_insensitive(true).build().unwrap())),
-    ("trivial", LazyLock::new(|| RegexBuilder::new("[^a-zA-Z]trivial[^a-zA-Z]").case_insensitive(true).build().unwrap())),
-    ("Ergibt das Sinn", LazyLock::new(|| RegexBuilder::new("[^a-zA-Z]ergibt\\s+das\\s+sinn[^a-zA-Z]").case_insensitive(true).build().unwrap())),
+/// A configured pattern compiled together with the name its count is reported under
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    name: String,
+    regex: Regex,
+    normalize_whitespace: bool,
+    collapse_hyphens: bool,
+}
+
+impl Pattern {
+    /// Compile a configured pattern with its context options applied
+    ///
+    /// `whole_word` uses word boundaries, which also hold at the start and end of the text and
+    /// consume nothing, so back-to-back occurrences all count. The context required by
+    /// `after_lowercase` is part of the match instead, as the regex crate has no lookbehind.
+    pub fn compile(config: &PatternConfig) -> anyhow::Result<Self> {
+        let leading = if config.after_lowercase {
+            // the lowercase class must stay case sensitive in case insensitive patterns, the
+            // whitespace after it already separates the match from the preceding word
+            "(?-i:\\p{Ll})\\s+"
+        } else if config.whole_word {
+            "\\b"
+        } else {
+            ""
+        };
+        let trailing = if config.whole_word { "\\b" } else { "" };
+        let mut pattern = format!("{leading}(?:{}){trailing}", config.regex);
+        if config.case_insensitive {
+            // as an inline flag instead of a builder option, so it carries over into the pattern set
+            pattern = format!("(?i){pattern}");
+        }
+        let regex = Regex::new(&pattern)
+            .with_context(|| format!("Invalid regex of pattern {}", config.name))?;
+        Ok(Self {
+            name: config.name.clone(),
+            regex,
+            normalize_whitespace: config.normalize_whitespace,
+            collapse_hyphens: config.collapse_hyphens,
+        })
+    }
+
+    /// Name the count of the pattern is reported under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Which copy of a text the pattern is matched against, 0 for the text as is
+    fn normalization(&self) -> usize {
+        usize::from(self.normalize_whitespace) | usize::from(self.collapse_hyphens) << 1
+    }
+
+    /// Byte ranges of all matches in `text`
+    ///
+    /// Matches in a normalized copy are mapped back to the span of the original text they were
+    /// normalized from.
+    pub fn find_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        self.find_ranges_in(&MatchText::new(text))
+    }
+
+    fn find_ranges_in(&self, text: &MatchText) -> Vec<Range<usize>> {
+        let (haystack, offsets) = text.for_pattern(self);
+        self.regex.find_iter(haystack)
+            .map(|found| match offsets {
+                Some(offsets) => offsets[found.start()]..offsets[found.end()],
+                None => found.range(),
+            })
+            .collect()
+    }
+}
+
+/// A text patterns are matched in, with the normalized copies they ask for made at most once
+/// and shared between them
+struct MatchText<'a> {
+    text: &'a str,
+    /// Normalized copies with the offsets of their bytes in `text`, by [`Pattern::normalization`]
+    normalized: [OnceCell<(String, Vec<usize>)>; 3],
+}
+
+impl<'a> MatchText<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            normalized: Default::default(),
+        }
+    }
+
+    /// The text the pattern is matched against, with the offsets of its bytes in the original
+    /// text if it is a normalized copy
+    fn for_pattern(&self, pattern: &Pattern) -> (&str, Option<&[usize]>) {
+        match pattern.normalization() {
+            0 => (self.text, None),
+            normalization => {
+                let (normalized, offsets) = self.normalized[normalization - 1]
+                    .get_or_init(|| normalize(self.text, pattern.normalize_whitespace, pattern.collapse_hyphens));
+                (normalized, Some(offsets))
+            }
+        }
+    }
+}
+
+/// Hyphen characters removed between letters by `collapse_hyphens`
+const HYPHENS: [char; 3] = ['-', '\u{2010}', '\u{2011}'];
+
+/// Normalize `text` for matching, along with the byte offset in `text` of every byte of the
+/// normalized copy and of its end
+fn normalize(text: &str, whitespace: bool, hyphens: bool) -> (String, Vec<usize>) {
+    let mut normalized = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut chars = text.char_indices().peekable();
+    let mut previous = None;
+    while let Some((offset, char)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        if hyphens && HYPHENS.contains(&char)
+            && previous.is_some_and(char::is_alphabetic) && next.is_some_and(char::is_alphabetic) {
+            continue;
+        }
+        if whitespace && char.is_whitespace() {
+            if previous.is_some_and(char::is_whitespace) {
+                continue;
+            }
+            normalized.push(' ');
+            offsets.push(offset);
+            previous = Some(char);
+            continue;
+        }
+        normalized.push(char);
+        offsets.extend(std::iter::repeat_n(offset, char.len_utf8()));
+        previous = Some(char);
+    }
+    offsets.push(text.len());
+    (normalized, offsets)
+}
+
+/// The patterns counted in every transcript, in the order their counts are reported
+#[derive(Debug)]
+struct Patterns {
+    patterns: Vec<Pattern>,
+    /// All patterns in one set, so a single scan tells which of them need to be located at all
+    set: RegexSet,
+}
+
+static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+
+/// Compile the configured patterns, which have to be set before any transcript is counted
+pub fn set_patterns(configs: &[PatternConfig]) -> anyhow::Result<()> {
+    let patterns = configs.iter()
+        .map(Pattern::compile)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some(duplicate) = patterns.iter().enumerate()
+        .find(|(index, pattern)| patterns[..*index].iter().any(|other| other.name == pattern.name)) {
+        return Err(anyhow!("Pattern {} is configured more than once", duplicate.1.name));
+    }
+    let set = RegexSet::new(patterns.iter().map(|pattern| pattern.regex.as_str()))?;
+    PATTERNS.set(Patterns { patterns, set })
+        .map_err(|_| anyhow!("Patterns are already set"))
+}
+
+fn patterns() -> &'static Patterns {
+    PATTERNS.get_or_init(|| {
+        let patterns = config::default_patterns().iter()
+            .map(|config| Pattern::compile(config).unwrap())
+            .collect::<Vec<_>>();
+        let set = RegexSet::new(patterns.iter().map(|pattern| pattern.regex.as_str())).unwrap();
+        Patterns { patterns, set }
+    })
+}
+
+/// The patterns that may match in `text`, in declaration order
+///
+/// Every copy of the text the patterns are matched against, usually just the text as is, is
+/// scanned once with the whole set to rule out the patterns without matches.
+fn matching_patterns(text: &MatchText) -> Vec<&'static Pattern> {
+    let patterns = patterns();
+    let mut scans: [Option<SetMatches>; 4] = Default::default();
+    patterns.patterns.iter()
+        .enumerate()
+        .filter(|(index, pattern)| scans[pattern.normalization()]
+            .get_or_insert_with(|| patterns.set.matches(text.for_pattern(pattern).0))
+            .matched(*index))
+        .map(|(_, pattern)| pattern)
+        .collect()
+}
+
+/// Reasons a transcript was deliberately not produced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptError {
+    /// The video has no captions and only captioned videos are processed
+    SkipNoCaption,
+    /// The episode is listed in `skip_episodes`
+    SkipEpisode,
+    /// The video is larger than `max_video_size`
+    SkipTooLarge {
+        /// Size stated by the server or, if it stated none, the size downloaded until the limit was exceeded
+        size: u64,
+        max_size: u64,
+    },
+}
+
+impl Display for TranscriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SkipNoCaption => write!(f, "Skipped video without captions"),
+            Self::SkipEpisode => write!(f, "Skipped episode listed in skip_episodes"),
+            Self::SkipTooLarge { size, max_size } => write!(f, "Skipped video of {size} bytes, exceeding the maximum video size of {max_size} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptError {}
+
+/// Fail with [`TranscriptError::SkipTooLarge`] if `size` exceeds the configured maximum video size
+fn check_video_size(size: u64, max_size: Option<u64>) -> Result<(), TranscriptError> {
+    match max_size {
+        Some(max_size) if size > max_size => Err(TranscriptError::SkipTooLarge { size, max_size }),
+        _ => Ok(()),
+    }
+}
+
+/// The videos of a course split by how they would be transcribed
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+    /// Videos with captions, which only need the caption file downloaded
+    pub captioned: usize,
+    /// Videos without captions, which need to be transcribed with whisper
+    pub whisper: usize,
+    /// Total recording time of the videos that need whisper
+    pub whisper_duration: Duration,
+    /// Videos needing whisper whose recording time is unknown
+    pub unknown_duration: usize,
+    /// Videos whose config could not be fetched
+    pub failed: usize,
+}
+
+/// What became of the videos of a run
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    /// Videos that were attempted, including failed ones
+    pub videos: usize,
+    /// Videos that could not be processed
+    pub failed: usize,
+    /// Links of videos skipped because they have no captions, with `captions_only`
+    pub captionless: Vec<String>,
+    /// Links of videos skipped because they exceed `max_video_size`
+    pub skipped: Vec<String>,
+}
+
+/// Serializes interactive prompts of concurrently processed videos
+static PROMPT_LOCK: Mutex<()> = Mutex::new(());
+
+async fn ask_for_fallback(question: String) -> anyhow::Result<bool> {
+    task::spawn_blocking(move || {
+        let _guard = PROMPT_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        eprint!("{question}");
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }).await?
+}
+
+/// Transcripts with fewer spoken words per minute are flagged as possibly incomplete
+const MIN_WORDS_PER_MINUTE: f64 = 20.0;
+/// Shorter transcripts are too short for a meaningful words per minute rate
+const MIN_REVIEW_DURATION_MS: u64 = 60_000;
+
+/// Selectors of the recording table body in order of preference, the first matches the table
+/// holding playback links wherever the layout puts it
+const RECORDING_TABLE_SELECTORS: [&str; 2] = [
+    "(//table[.//@data-episode-id or .//@data-href or .//td/a[contains(@href, 'e=')]]/tbody)[1]",
+    "(//*[@id='region-main']//table/tbody)[1]",
 ];
 
+/// Characters of page HTML logged when an expected element is missing from it
+const PAGE_SNIPPET_CHARS: usize = 2_000;
+
+/// Characters of the video config script quoted when it can't be parsed
+const SCRIPT_SNIPPET_CHARS: usize = 200;
+
+/// The object assigned to `window.episode`, regardless of spacing, CDATA or other wrappers
+static EPISODE_ASSIGNMENT: OnceLock<Regex> = OnceLock::new();
+
+
+/// Permits of a concurrency limit, of which there is at least one so work is never held back
+/// forever
+pub fn permits(limit: usize) -> Arc<Semaphore> {
+    Arc::new(Semaphore::new(limit.max(1)))
+}
+
+/// Count the matches of every pattern in declaration order
+///
+/// Patterns without any match, usually most of them, are ruled out in a single scan of the
+/// transcript instead of one scan per pattern.
+pub fn count_patterns(transcript: &str) -> IndexMap<String, usize> {
+    let mut counts = pattern_names()
+        .map(|name| (name.to_string(), 0))
+        .collect::<IndexMap<_, _>>();
+    let transcript = MatchText::new(transcript);
+    for pattern in matching_patterns(&transcript) {
+        let matches = pattern.find_ranges_in(&transcript).len();
+        counts.insert(pattern.name.clone(), matches);
+        tracing::debug!("Found {matches} {}s", pattern.name);
+    }
+    counts
+}
+
+/// Count the matches of each of the given patterns in order, independent of the configured ones
+pub fn count_patterns_with(text: &str, patterns: &[Pattern]) -> IndexMap<String, usize> {
+    let text = MatchText::new(text);
+    patterns.iter()
+        .map(|pattern| (pattern.name.clone(), pattern.find_ranges_in(&text).len()))
+        .collect()
+}
+
+/// Byte ranges of every match of every pattern in declaration order
+pub fn match_ranges(text: &str) -> Vec<(&'static str, Range<usize>)> {
+    let text = MatchText::new(text);
+    matching_patterns(&text)
+        .into_iter()
+        .flat_map(|pattern| pattern.find_ranges_in(&text)
+            .into_iter()
+            .map(|range| (pattern.name.as_str(), range)))
+        .collect()
+}
+
+/// A pattern match quoted together with the transcript text around it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MatchContext {
+    pub pattern: String,
+    /// The words of the match and up to the requested number of words before and after it
+    pub snippet: String,
+    /// Byte offset of the match in the transcript
+    pub offset: usize,
+}
+
+/// Every match of every pattern in `text` with `context_words` words around it, in text order
+pub fn match_contexts(text: &str, context_words: usize) -> Vec<MatchContext> {
+    let words = word_ranges(text);
+    let mut contexts = match_ranges(text).into_iter()
+        .map(|(pattern, range)| {
+            // the words the match overlaps, a match within whitespace only has the following one
+            let first = words.partition_point(|word| word.end <= range.start);
+            let last = words.partition_point(|word| word.start < range.end).max(first + 1);
+            let start = words.get(first.saturating_sub(context_words)).map_or(range.start, |word| word.start);
+            let end = words.get((last + context_words).min(words.len()).saturating_sub(1))
+                .map_or(range.end, |word| word.end)
+                .max(range.end);
+            MatchContext {
+                pattern: pattern.to_string(),
+                snippet: text[start.min(range.start)..end].split_whitespace().collect::<Vec<_>>().join(" "),
+                offset: range.start,
+            }
+        })
+        .collect::<Vec<_>>();
+    contexts.sort_by_key(|context| context.offset);
+    contexts
+}
+
+/// Byte ranges of the whitespace separated words of `text`
+fn word_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut words = vec![];
+    let mut start = None;
+    for (offset, char) in text.char_indices() {
+        match (char.is_whitespace(), start) {
+            (true, Some(word_start)) => {
+                words.push(word_start..offset);
+                start = None;
+            }
+            (false, None) => start = Some(offset),
+            _ => {}
+        }
+    }
+    if let Some(word_start) = start {
+        words.push(word_start..text.len());
+    }
+    words
+}
+
+/// Count non-overlapping occurrences of the sequence's patterns in order within its time window
+///
+/// Matches are only timed per transcript segment, so patterns within the same segment count as
+/// being in order regardless of their position in it.
+pub fn count_sequence(matches: &[Match], sequence: &Sequence) -> usize {
+    let Some((first, rest)) = sequence.patterns.split_first() else {
+        return 0;
+    };
+    let window_ms = sequence.window_seconds * 1000;
+    let mut matches = matches.iter().collect::<Vec<_>>();
+    matches.sort_by_key(|found| found.start_ms);
+
+    let mut count = 0;
+    let mut next = 0;
+    while let Some(offset) = matches[next..].iter().position(|found| found.pattern == *first) {
+        let start = next + offset;
+        let start_ms = matches[start].start_ms;
+        let mut position = start;
+        let complete = rest.iter().all(|pattern| {
+            let found = matches[position + 1..].iter()
+                .take_while(|found| found.start_ms <= start_ms + window_ms)
+                .position(|found| found.pattern == *pattern);
+            if let Some(offset) = found {
+                position += 1 + offset;
+            }
+            found.is_some()
+        });
+        if complete {
+            count += 1;
+            next = position + 1;
+        } else {
+            next = start + 1;
+        }
+    }
+    count
+}
+
+/// Names of all patterns in the order their counts appear in a [`DataRow`]
+pub fn pattern_names() -> impl Iterator<Item = &'static str> {
+    patterns().patterns.iter().map(|pattern| pattern.name.as_str())
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DataRow {
     title: String,
     link: String,
+    /// Opencast page of the course the video was listed on, empty if it was not found on a course page
+    #[serde(default)]
+    course: String,
     transcript: String,
-    defacto: usize,
-    trivial: usize,
-    sinn: usize,
+    /// Match counts keyed by pattern name, kept in pattern declaration order
+    counts: IndexMap<String, usize>,
+    word_count: usize,
+    /// Time span covered by the transcript segments
+    duration_ms: u64,
+    /// Where the transcript came from
+    #[serde(default)]
+    source: TranscriptSource,
+    /// Occurrence counts keyed by sequence name, in configuration order
+    sequence_counts: IndexMap<String, usize>,
+    /// Match counts in the slide text, if slides were recognized
+    #[cfg(feature = "ocr")]
+    slide_counts: Option<IndexMap<String, usize>>,
+    /// Pattern matches with the time span of the segment they occurred in
+    matches: Vec<Match>,
+    /// The timed caption cues or whisper segments the transcript was joined from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    segments: Option<Vec<Segment>>,
+    /// Non-fatal issues encountered while processing the video
+    warnings: Vec<String>,
+}
+
+impl DataRow {
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn link(&self) -> &str {
+        &self.link
+    }
+
+    pub fn course(&self) -> &str {
+        &self.course
+    }
+
+    pub fn transcript(&self) -> &str {
+        &self.transcript
+    }
+
+    pub fn counts(&self) -> &IndexMap<String, usize> {
+        &self.counts
+    }
+
+    pub fn source(&self) -> TranscriptSource {
+        self.source
+    }
+
+    /// Average spoken words per minute, if the transcript covers any time at all
+    pub fn words_per_minute(&self) -> Option<f64> {
+        (self.duration_ms > 0)
+            .then(|| self.word_count as f64 / (self.duration_ms as f64 / 60_000.0))
+    }
+
+    /// Treat the transcripts of all rows as a single document and count matches over the whole text
+    pub fn concatenate(title: impl Into<String>, link: impl Into<String>, rows: &[DataRow]) -> DataRow {
+        let transcript = rows.iter()
+            .map(|row| row.transcript.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let course = match rows {
+            [first, rest @ ..] if rest.iter().all(|row| row.course == first.course) => first.course.clone(),
+            _ => String::new(),
+        };
+        DataRow {
+            title: title.into(),
+            link: link.into(),
+            course,
+            counts: count_patterns(&transcript),
+            word_count: rows.iter().map(|row| row.word_count).sum(),
+            duration_ms: rows.iter().map(|row| row.duration_ms).sum(),
+            source: TranscriptSource::Unknown,
+            sequence_counts: rows.iter()
+                .fold(IndexMap::new(), |mut totals, row| {
+                    for (name, count) in &row.sequence_counts {
+                        *totals.entry(name.clone()).or_default() += count;
+                    }
+                    totals
+                }),
+            #[cfg(feature = "ocr")]
+            slide_counts: rows.iter()
+                .filter_map(|row| row.slide_counts.as_ref())
+                .fold(None, |mut totals: Option<IndexMap<String, usize>>, counts| {
+                    let totals_ref = totals.get_or_insert_with(IndexMap::new);
+                    for (name, count) in counts {
+                        *totals_ref.entry(name.clone()).or_default() += count;
+                    }
+                    totals
+                }),
+            transcript,
+            // timestamps of different videos cannot be placed on a common timeline
+            matches: vec![],
+            segments: None,
+            warnings: rows.iter()
+                .flat_map(|row| row.warnings.iter().cloned())
+                .collect(),
+        }
+    }
+
+    /// One [concatenated](Self::concatenate) row per course, in the order the courses first occur
+    pub fn course_totals(rows: Vec<DataRow>) -> Vec<DataRow> {
+        let mut courses = IndexMap::<String, Vec<DataRow>>::new();
+        for row in rows {
+            courses.entry(row.course.clone()).or_default().push(row);
+        }
+        courses.into_iter()
+            .map(|(course, rows)| DataRow::concatenate("Course total", course, &rows))
+            .collect()
+    }
+
+    pub fn csv_header<'a, 'b>(patterns: impl IntoIterator<Item = &'a str>, sequences: impl IntoIterator<Item = &'b str>) -> StringRecord {
+        let patterns = patterns.into_iter().collect::<Vec<_>>();
+        let mut header = StringRecord::from(vec!["title", "link", "course", "transcript", "word_count", "words_per_minute"]);
+        header.extend(patterns.iter());
+        header.extend(patterns.iter().map(|pattern| format!("{pattern} per 1000 words")));
+        header.extend(sequences);
+        #[cfg(feature = "ocr")]
+        header.extend(patterns.iter().map(|pattern| format!("slides: {pattern}")));
+        header
+    }
+
+    /// The CSV record of this row, with control characters stripped from the transcript if `sanitize` is set
+    /// and the transcript cut to `max_transcript_chars`, counts always refer to the full transcript
+    pub fn csv_record(&self, sanitize: bool, max_transcript_chars: Option<usize>) -> StringRecord {
+        let transcript = match max_transcript_chars {
+            Some(max_chars) => output::truncate_field(&self.transcript, max_chars),
+            None => self.transcript.as_str().into(),
+        };
+        let transcript = if sanitize {
+            output::sanitize_field(&transcript).into_owned().into()
+        } else {
+            transcript
+        };
+        let mut record = StringRecord::from(vec![self.title.as_str(), self.link.as_str(), self.course.as_str(), &transcript]);
+        record.push_field(&self.word_count.to_string());
+        record.push_field(&self.words_per_minute()
+            .map(|words_per_minute| format!("{words_per_minute:.1}"))
+            .unwrap_or_default());
+        record.extend(self.counts.values().map(|count| count.to_string()));
+        record.extend(rate_fields(&self.counts, self.word_count));
+        record.extend(self.sequence_counts.values().map(|count| count.to_string()));
+        #[cfg(feature = "ocr")]
+        match &self.slide_counts {
+            Some(slide_counts) => record.extend(slide_counts.values().map(|count| count.to_string())),
+            None => record.extend(self.counts.keys().map(|_| "")),
+        }
+        record
+    }
+
+    pub fn timestamps_csv_header() -> StringRecord {
+        StringRecord::from(vec!["title", "link", "pattern", "start", "end", "text", "snippet"])
+    }
+
+    /// One record per match of this row, with timestamps in the given format
+    pub fn timestamps_csv_records(&self, format: TimestampFormat) -> impl Iterator<Item = StringRecord> + '_ {
+        self.matches.iter()
+            .map(move |found| StringRecord::from(vec![
+                self.title.as_str(),
+                self.link.as_str(),
+                found.pattern.as_str(),
+                &transcript::format_timestamp(found.start_ms, format),
+                &transcript::format_timestamp(found.end_ms, format),
+                found.text.as_str(),
+                found.snippet.as_str(),
+            ]))
+    }
+
+    pub fn contexts_csv_header() -> StringRecord {
+        StringRecord::from(vec!["title", "link", "pattern", "offset", "snippet"])
+    }
+
+    /// One record per match in the transcript of this row, quoted with `context_words` words
+    /// around it
+    pub fn contexts_csv_records(&self, context_words: usize) -> impl Iterator<Item = StringRecord> + '_ {
+        match_contexts(&self.transcript, context_words).into_iter()
+            .map(move |context| StringRecord::from(vec![
+                self.title.as_str(),
+                self.link.as_str(),
+                context.pattern.as_str(),
+                &context.offset.to_string(),
+                context.snippet.as_str(),
+            ]))
+    }
+
+    pub fn warnings_csv_header() -> StringRecord {
+        StringRecord::from(vec!["title", "link", "warning"])
+    }
+
+    /// One record per warning of this row
+    pub fn warnings_csv_records(&self) -> impl Iterator<Item = StringRecord> + '_ {
+        self.warnings.iter()
+            .map(|warning| StringRecord::from(vec![self.title.as_str(), self.link.as_str(), warning.as_str()]))
+    }
 }
 
 impl Into<ShortenedDataRow> for DataRow {
@@ -37,9 +669,9 @@ impl Into<ShortenedDataRow> for DataRow {
         ShortenedDataRow {
             title: self.title,
             link: self.link,
-            defacto: self.defacto,
-            trivial: self.trivial,
-            sinn: self.sinn,
+            course: self.course,
+            word_count: self.word_count,
+            counts: self.counts,
         }
     }
 }
@@ -48,38 +680,211 @@ impl Into<ShortenedDataRow> for DataRow {
 pub struct ShortenedDataRow {
     title: String,
     link: String,
-    defacto: usize,
-    trivial: usize,
-    sinn: usize,
+    course: String,
+    word_count: usize,
+    counts: IndexMap<String, usize>,
+}
+
+impl ShortenedDataRow {
+    pub fn csv_header<'a>(patterns: impl IntoIterator<Item = &'a str>) -> StringRecord {
+        let patterns = patterns.into_iter().collect::<Vec<_>>();
+        let mut header = StringRecord::from(vec!["title", "link", "course", "word_count"]);
+        header.extend(patterns.iter());
+        header.extend(patterns.iter().map(|pattern| format!("{pattern} per 1000 words")));
+        header
+    }
+
+    pub fn csv_record(&self) -> StringRecord {
+        let mut record = StringRecord::from(vec![self.title.as_str(), self.link.as_str(), self.course.as_str()]);
+        record.push_field(&self.word_count.to_string());
+        record.extend(self.counts.values().map(|count| count.to_string()));
+        record.extend(rate_fields(&self.counts, self.word_count));
+        record
+    }
+}
+
+/// Matches per 1000 words, comparable between lectures of different lengths, if there are any words
+pub fn rate_per_thousand_words(count: usize, word_count: usize) -> Option<f64> {
+    (word_count > 0).then(|| count as f64 * 1000.0 / word_count as f64)
+}
+
+/// The CSV fields of the rates of all counts, empty for transcripts without words
+fn rate_fields(counts: &IndexMap<String, usize>, word_count: usize) -> impl Iterator<Item = String> + '_ {
+    counts.values()
+        .map(move |&count| rate_per_thousand_words(count, word_count)
+            .map(|rate| format!("{rate:.2}"))
+            .unwrap_or_default())
+}
+
+/// A transcript as fetched, cached so matches can be counted again without fetching it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CachedTranscript {
+    title: String,
+    link: String,
+    #[serde(default)]
+    source: TranscriptSource,
+    transcript: Transcript,
+}
+
+/// Sample rate whisper.cpp models are trained on
+pub const WHISPER_SAMPLE_RATE: u32 = 16_000;
+
+/// Target format audio is resampled to before transcription
+///
+/// Only f32 sample formats are supported. Multi-channel audio is returned interleaved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample: Sample,
+    pub channel_layout: ChannelLayout,
+    pub rate: u32,
+}
+
+impl Default for AudioFormat {
+    fn default() -> Self {
+        Self {
+            sample: Sample::F32(sample::Type::Planar),
+            channel_layout: ChannelLayout::MONO,
+            rate: WHISPER_SAMPLE_RATE,
+        }
+    }
 }
 
+/// Length of the independently transcribed chunks when resuming whisper transcriptions without a
+/// configured chunk length
+const RESUME_CHUNK_SECONDS: u32 = 300;
+
+/// A transcribed span of audio as persisted for resumable whisper transcriptions
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct TranscribedChunk {
+    start_ms: u64,
+    end_ms: u64,
+    segments: Vec<Segment>,
+}
+
+/// The whisper model, loaded by the first transcription
+static WHISPER_CONTEXT: OnceLock<WhisperContext> = OnceLock::new();
+
 #[derive(Debug, Copy, Clone)]
 struct STTContext;
 
 impl STTContext {
-    const CONTEXT: LazyLock<WhisperContext> = LazyLock::new(|| {
-        ffmpeg_next::init().unwrap();
+    /// The whisper model at `model_path`, or the one loaded before
+    fn context(model_path: &Path) -> anyhow::Result<&'static WhisperContext> {
+        if let Some(context) = WHISPER_CONTEXT.get() {
+            return Ok(context);
+        }
+        ffmpeg_next::init()?;
 
         whisper_rs::install_whisper_tracing_trampoline();
-        let model_path = std::env::var("WHISPER_MODEL").unwrap();
-        WhisperContext::new_with_params(
-            &model_path,
+        let model = model_path.to_str()
+            .ok_or(anyhow!("Whisper model path {} is not valid UTF-8", model_path.display()))?;
+        let context = WhisperContext::new_with_params(
+            model,
             WhisperContextParameters::default()
-        ).unwrap()
-        
-    });
+        ).with_context(|| format!("Failed to load whisper model {}", model_path.display()))?;
+        Ok(WHISPER_CONTEXT.get_or_init(|| context))
+    }
     
-    async fn get_whisper_transcript(path: impl AsRef<Path>) -> anyhow::Result<String> {
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-        params.set_language(Some("de"));
-        params.set_translate(false);
+    async fn get_whisper_transcript(path: impl AsRef<Path>, model_path: PathBuf, resume_path: Option<PathBuf>, decoding: WhisperDecoding, chunking: WhisperChunking) -> anyhow::Result<Transcript> {
+        let audio_data = Self::get_audio_data_blocking(path, AudioFormat::default()).await?;
+
+        // inference is CPU bound and would otherwise stall a runtime worker for minutes
+        run_blocking(move || {
+            let context = Self::context(&model_path)?;
+            match (resume_path, chunking.chunk_seconds) {
+                (None, None) => Self::transcribe(context, &audio_data, &decoding),
+                (resume_path, chunk_seconds) => {
+                    let chunk_seconds = chunk_seconds.unwrap_or(RESUME_CHUNK_SECONDS);
+                    Self::transcribe_chunked(context, &audio_data, chunk_seconds, resume_path.as_deref(), &decoding, chunking)
+                }
+            }
+        }).await
+    }
+
+    /// Transcribe in chunks of `chunk_seconds`, so long recordings are never decoded in one pass
+    ///
+    /// With a `resume_path`, every finished chunk is appended to a JSONL file there and chunks
+    /// already present in it are skipped. Chunks extend into the next one by the configured
+    /// overlap. Their segments are persisted as transcribed and only stitched at the seams while
+    /// assembling the transcript.
+    fn transcribe_chunked(context: &WhisperContext, audio_data: &[f32], chunk_seconds: u32, resume_path: Option<&Path>, decoding: &WhisperDecoding, chunking: WhisperChunking) -> anyhow::Result<Transcript> {
+        let mut finished = Vec::new();
+        let mut progress = None;
+        if let Some(resume_path) = resume_path {
+            if resume_path.exists() {
+                let data = std::fs::read_to_string(resume_path)?;
+                for line in data.lines() {
+                    match serde_json::from_str::<TranscribedChunk>(line) {
+                        Ok(chunk) => finished.push(chunk),
+                        // a crash while appending leaves at most the last line truncated
+                        Err(err) => tracing::warn!("Discarding unreadable whisper progress entry: {err}"),
+                    }
+                }
+                tracing::info!("Resuming whisper transcription with {} finished chunks", finished.len());
+            }
+
+            // rewrite the progress file to drop any truncated entries before appending to it
+            let mut file = File::create(resume_path)?;
+            for chunk in &finished {
+                writeln!(file, "{}", serde_json::to_string(chunk)?)?;
+            }
+            progress = Some(file);
+        }
+
+        let chunk_length = chunk_seconds.max(1) as usize * WHISPER_SAMPLE_RATE as usize;
+        let overlap_length = chunking.overlap_seconds as usize * WHISPER_SAMPLE_RATE as usize;
+        let chunk_count = audio_data.len().div_ceil(chunk_length);
+        let mut transcript = Transcript::default();
+        // end of the previous chunk, which the current one overlaps from its start up to
+        let mut previous_end_ms = 0;
+        for index in 0..chunk_count {
+            let start = index * chunk_length;
+            let audio_chunk = &audio_data[start..(start + chunk_length + overlap_length).min(audio_data.len())];
+            let start_ms = start as u64 * 1000 / WHISPER_SAMPLE_RATE as u64;
+            let end_ms = start_ms + audio_chunk.len() as u64 * 1000 / WHISPER_SAMPLE_RATE as u64;
+            let overlap = start_ms..previous_end_ms.max(start_ms);
+            previous_end_ms = end_ms;
+
+            let cached = finished.iter().find(|chunk| chunk.start_ms == start_ms && chunk.end_ms == end_ms);
+            if let Some(chunk) = cached {
+                seam::stitch(&mut transcript.segments, chunk.segments.clone(), overlap, chunking.seam);
+                continue;
+            }
+
+            tracing::info!("Transcribing chunk {}/{chunk_count}", index + 1);
+            let mut segments = Self::transcribe(context, audio_chunk, decoding)?.segments;
+            for segment in &mut segments {
+                segment.start_ms += start_ms;
+                segment.end_ms += start_ms;
+            }
+            let chunk = TranscribedChunk {
+                start_ms,
+                end_ms,
+                segments,
+            };
+            if let Some(progress) = &mut progress {
+                writeln!(progress, "{}", serde_json::to_string(&chunk)?)?;
+                progress.flush()?;
+            }
+            seam::stitch(&mut transcript.segments, chunk.segments, overlap, chunking.seam);
+        }
+
+        Ok(transcript)
+    }
 
-        let audio_data = Self::get_audio_data(path)?;
+    fn transcribe(context: &WhisperContext, audio_data: &[f32], decoding: &WhisperDecoding) -> anyhow::Result<Transcript> {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: decoding.best_of });
+        params.set_language(Some(decoding.language.as_deref().unwrap_or("auto")));
+        params.set_translate(false);
+        params.set_temperature(decoding.temperature);
+        params.set_temperature_inc(decoding.temperature_inc);
+        params.set_entropy_thold(decoding.entropy_threshold);
+        params.set_logprob_thold(decoding.logprob_threshold);
 
-        let mut state = Self::CONTEXT.create_state()?;
-        state.full(params, &audio_data[..])?;
+        let mut state = context.create_state()?;
+        state.full(params, audio_data)?;
 
-        let mut result = String::new();
+        let mut transcript = Transcript::default();
         let num_segments = state
             .full_n_segments()
             .expect("failed to get number of segments");
@@ -87,7 +892,6 @@ impl STTContext {
             let segment = state
                 .full_get_segment_text(i)
                 .expect("failed to get segment");
-            result.push_str(&segment);
             let start_timestamp = state
                 .full_get_segment_t0(i)
                 .expect("failed to get segment start timestamp");
@@ -95,273 +899,2165 @@ impl STTContext {
                 .full_get_segment_t1(i)
                 .expect("failed to get segment end timestamp");
             tracing::trace!("[{} - {}]: {}", start_timestamp, end_timestamp, segment);
+            // whisper timestamps are in centiseconds
+            transcript.segments.push(Segment {
+                start_ms: start_timestamp.max(0) as u64 * 10,
+                end_ms: end_timestamp.max(0) as u64 * 10,
+                text: segment.trim().to_string(),
+            });
         }
         
-        Ok(result)
-    }
-
-    fn get_audio_data(path: impl AsRef<Path>) -> anyhow::Result<Vec<f32>> {
-        let mut ictx = input(&path)?;
-        let input = ictx
-            .streams()
-            .best(Type::Audio)
-            .ok_or(ffmpeg_next::Error::StreamNotFound)?;
-        let stream_index = input.index();
-
-        let context_decoder = ffmpeg_next::codec::context::Context::from_parameters(input.parameters())?;
-        let mut decoder = context_decoder.decoder().audio()?;
-        let mut resampler = decoder.resampler(
-            Sample::F32(sample::Type::Planar),
-            channel_layout::ChannelLayout::MONO,
-            16_000
-        )?;
-
-        let mut data = vec![];
-
-        for (stream, packet) in ictx.packets() {
-            if stream.index() == stream_index {
-                decoder.send_packet(&packet)?;
-                let mut decoded = Audio::empty();
-                while decoder.receive_frame(&mut decoded).is_ok() {
-                    let mut resampled = Audio::empty();
-                    resampler.run(&decoded, &mut resampled)?;
+        Ok(transcript)
+    }
+
+    /// Decode audio on the blocking thread pool to keep the async runtime responsive
+    async fn get_audio_data_blocking(path: impl AsRef<Path>, format: AudioFormat) -> anyhow::Result<Vec<f32>> {
+        let path = path.as_ref().to_owned();
+        run_blocking(move || decode_audio(path, format)).await
+    }
+}
+
+/// Run CPU bound work on the blocking thread pool within the current span, so the runtime workers
+/// keep downloading and parsing in the meantime
+async fn run_blocking<T: Send + 'static>(work: impl FnOnce() -> anyhow::Result<T> + Send + 'static) -> anyhow::Result<T> {
+    let span = Span::current();
+    task::spawn_blocking(move || span.in_scope(work)).await?
+}
+
+/// Decode the best audio stream of a media file, resampled to `format`
+///
+/// This blocks for as long as decoding takes, async callers should run it on the blocking thread
+/// pool.
+pub fn decode_audio(path: impl AsRef<Path>, format: AudioFormat) -> anyhow::Result<Vec<f32>> {
+    let AudioFormat { sample, channel_layout, rate } = format;
+    let packed = match sample {
+        Sample::F32(sample::Type::Packed) => true,
+        Sample::F32(sample::Type::Planar) => false,
+        _ => return Err(anyhow!("Unsupported sample format {sample:?}, only f32 samples can be collected")),
+    };
+    if rate != WHISPER_SAMPLE_RATE {
+        tracing::warn!("Resampling audio to {rate}Hz, the default whisper model expects {WHISPER_SAMPLE_RATE}Hz");
+    }
+
+    let mut ictx = input(&path)?;
+    let input = ictx
+        .streams()
+        .best(Type::Audio)
+        .ok_or(ffmpeg_next::Error::StreamNotFound)?;
+    let stream_index = input.index();
+
+    let context_decoder = ffmpeg_next::codec::context::Context::from_parameters(input.parameters())?;
+    let mut decoder = context_decoder.decoder().audio()?;
+    let mut resampler = decoder.resampler(sample, channel_layout, rate)?;
+    let channels = channel_layout.channels() as usize;
+
+    let mut data = vec![];
+
+    for (stream, packet) in ictx.packets() {
+        if stream.index() == stream_index {
+            decoder.send_packet(&packet)?;
+            let mut decoded = Audio::empty();
+            while decoder.receive_frame(&mut decoded).is_ok() {
+                let mut resampled = Audio::empty();
+                resampler.run(&decoded, &mut resampled)?;
+                if packed {
+                    // packed frames hold all channels interleaved in the first plane
+                    let bytes = &resampled.data(0)[..resampled.samples() * channels * size_of::<f32>()];
+                    data.extend(bytes.chunks_exact(size_of::<f32>())
+                        .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap())));
+                } else if channels == 1 {
                     data.extend_from_slice(resampled.plane(0));
+                } else {
+                    let planes = (0..channels)
+                        .map(|channel| resampled.plane::<f32>(channel))
+                        .collect::<Vec<_>>();
+                    for index in 0..resampled.samples() {
+                        data.extend(planes.iter().map(|plane| plane[index]));
+                    }
                 }
             }
         }
-
-        Ok(data)
     }
+
+    Ok(data)
 }
 
 #[derive(Debug, Clone)]
 pub struct DefactoClient {
     pub client: TUWElClient,
+    /// Opencast activity pages of the processed courses
+    pub course_urls: Vec<Url>,
     pub cache_path: PathBuf,
+    pub cache_layout: CacheLayout,
+    pub clip_export: Option<ClipExport>,
+    pub search_api_url: Option<Url>,
+    /// Caption languages in order of preference
+    pub caption_languages: Vec<String>,
+    /// Fall back to captions of any language if none of the preferred ones exist
+    pub allow_any_language: bool,
+    /// Pattern sequences counted within a time window
+    pub sequences: Vec<Sequence>,
+    /// Maximum number of video configs fetched at once before processing starts
+    pub discovery_concurrency: usize,
+    /// Retries of requests failing with a connection or server error
+    pub max_retries: u32,
+    /// Time limit of a whole video download, which overrides the shorter limit of other requests
+    pub download_timeout: Duration,
+    /// Time limit of establishing a connection to a whisper server, which is not requested
+    /// through the session client
+    pub connect_timeout: Duration,
+    /// Limits the rate of all video downloads together, if set
+    ///
+    /// Recordings ffmpeg streams without `download_video` are neither limited nor counted in
+    /// [`bandwidth::total`], as their bytes never pass through the client.
+    pub download_throttle: Option<Arc<bandwidth::Throttle>>,
+    /// Limits the number of videos processed at once
+    pub video_permits: Arc<Semaphore>,
+    /// Limits the number of whisper transcriptions at once, shared by all videos
+    pub whisper_permits: Arc<Semaphore>,
+    /// Transcript cleanup stages, applied in order before matching
+    pub post_processors: Vec<PostProcessor>,
+    /// Persist whisper progress per episode and continue from it on the next run
+    pub resume_whisper: bool,
+    /// Extracts the episode JSON from video page scripts instead of `window.episode`
+    pub video_config_pattern: Option<Regex>,
+    /// Serve recording lists and video pages from a snapshot instead of requesting them
+    pub snapshot: Option<Snapshot>,
+    /// Remote transcription endpoint replacing the local whisper model
+    pub whisper_server: Option<WhisperServer>,
+    /// GGML model of the local whisper, `WHISPER_MODEL` is used if unset
+    pub whisper_model: Option<PathBuf>,
+    pub whisper_decoding: WhisperDecoding,
+    pub whisper_chunking: WhisperChunking,
+    /// Which source of the main audio stream is transcribed
+    pub audio_stream_preference: AudioStreamPreference,
+    pub fallback_policy: FallbackPolicy,
+    /// Episode ids that are never processed, e.g. because they were verified manually
+    pub skip_episodes: Vec<String>,
+    /// Only process videos with captions, skipping the others without transcribing them
+    pub captions_only: bool,
+    /// Process these playback links instead of all recordings of the course
+    pub captionless_links: Option<Vec<String>>,
+    /// Count matches in the OCR text of slide videos
+    #[cfg(feature = "ocr")]
+    pub slide_ocr: bool,
+    /// Replace downloaded videos by small mp3 files of their audio before transcribing
+    pub transcode_first: bool,
+    /// Download videos into the cache before transcribing them instead of letting ffmpeg stream them
+    pub download_video: bool,
+    /// Videos larger than this many bytes are skipped instead of downloaded or streamed
+    pub max_video_size: Option<u64>,
+    /// Directory to write WebVTT captions of whisper transcripts into
+    pub vtt_export: Option<PathBuf>,
+    /// Directory to write word diffs between captions and whisper transcripts into
+    pub compare: Option<PathBuf>,
+    /// Keep the timed segments of every transcript in its row
+    pub include_segments: bool,
+    /// Fetch every transcript again instead of reusing the ones cached by earlier runs
+    pub no_transcript_cache: bool,
+    /// Print how many videos are done to stderr while processing them
+    pub show_progress: bool,
+    /// Playback links processed by an earlier run that is resumed
+    pub done_links: HashSet<String>,
 }
 
 impl DefactoClient {
-    pub async fn do_stuff(&self) -> anyhow::Result<Vec<DataRow>> {
-        let links = self.get_video_links("https://tuwel.tuwien.ac.at/mod/opencast/view.php?id=2418332").await?;
+    /// The `dir` subdirectory of the cache, created if missing
+    fn cache_dir(cache_path: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+        let dir = cache_path.join(dir);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
 
-        tracing::debug!(?links);
-        let handles = links.into_iter()
-            .map(|link| {
-                let client = self.clone();
-                task::spawn(async move {
-                    client.get_data(link).await
-                })
-            })
-            .collect::<Vec<_>>();
-        
-        let mut data = Vec::with_capacity(handles.len());
-        
-        for handle in handles {
-            match handle.await? {
-                Ok(result) => data.push(result),
-                Err(err) => tracing::error!(?err)
-            }
+    /// Path of the persisted login session, moving a session file from the former flat cache
+    /// layout into the sessions directory
+    pub fn session_path(cache_path: &Path, cache_layout: &CacheLayout) -> std::io::Result<PathBuf> {
+        let session_path = Self::cache_dir(cache_path, &cache_layout.sessions)?.join("session.json");
+        let legacy_path = cache_path.join(".session.json");
+        if legacy_path.exists() && !session_path.exists() {
+            tracing::info!("Moving session file {} to {}", legacy_path.display(), session_path.display());
+            std::fs::rename(&legacy_path, &session_path)?;
         }
-
-        Ok(data)
+        Ok(session_path)
     }
-    
-    pub async fn get_data(&self, video_page: impl IntoUrl) -> anyhow::Result<DataRow> {
-        let link = video_page.as_str().to_string();
-        tracing::info!(link, "Getting video config");
-        let video_config = self.get_video_config(video_page).await?;
-
-        let title = video_config["metadata"]["title"].as_str()
-            .ok_or(anyhow!("Could not find title in video metadata"))?;
-        let span = span!(Level::INFO, "video", title);
 
-        async {
-            let transcript = self.get_transcript(&video_config).await?;
-            tracing::trace!(transcript);
-
-            let mut counts = [0; 3];
-            for (index, (name, pattern)) in PATTERNS.iter().enumerate() {
-                let matches = pattern.find_iter(&transcript)
-                    .count();
-                counts[index] = matches;
-                tracing::debug!("Found {matches} {name}s");
-            }
-
-            Ok(DataRow {
-                title: title.to_string(),
-                link,
-                transcript,
-                defacto: counts[0],
-                trivial: counts[1],
-                sinn: counts[2],
-            })
-        }
-            .instrument(span)
-            .await
+    /// Directory of downloaded videos
+    pub fn videos_dir(&self) -> std::io::Result<PathBuf> {
+        Self::cache_dir(&self.cache_path, &self.cache_layout.videos)
     }
 
-    pub async fn get_video_links(&self, link: impl IntoUrl) -> anyhow::Result<Vec<String>> {
-        let recordings = self.client.get(link)
-            .send().await?
-            .error_for_status()?
-            .xpath().await?;
+    /// Directory of whisper transcription progress
+    pub fn transcripts_dir(&self) -> std::io::Result<PathBuf> {
+        Self::cache_dir(&self.cache_path, &self.cache_layout.transcripts)
+    }
 
-        let links = recordings.select("/html/body/div[2]/div[4]/div/div/div[2]/div/section/div[2]/div[2]/table/tbody")?
-            .as_node()
-            .ok_or(anyhow!("Could not find video link in table"))?;
-        let links = links
-            .findnodes("tr/td/a")?
-            .iter()
-            .filter_map(|node| node.attr("href"))
-            .collect();
+    /// Directory of audio tracks extracted from videos
+    pub fn audio_dir(&self) -> std::io::Result<PathBuf> {
+        Self::cache_dir(&self.cache_path, &self.cache_layout.audio)
+    }
 
-        Ok(links)
+    /// Replace the transcript cleanup stages, e.g. to reorder the built-in ones or add custom ones
+    pub fn with_post_processors(mut self, post_processors: Vec<PostProcessor>) -> Self {
+        self.post_processors = post_processors;
+        self
     }
 
-    pub async fn get_video_config(&self, link: impl IntoUrl) -> anyhow::Result<JsonValue> {
-        let video_page = self.client.get(link)
-            .send().await?
-            .error_for_status()?
-            .xpath().await?;
+    /// Process all videos, handing every row to `on_row` in link order as soon as it is done, and
+    /// return the links of videos skipped for lacking captions
+    ///
+    /// Rows are not kept, so only the results of videos still being processed are held at once.
+    pub async fn do_stuff(&self, mut on_row: impl FnMut(DataRow) -> anyhow::Result<()>) -> anyhow::Result<RunReport> {
+        let links = match &self.captionless_links {
+            Some(links) => links.iter()
+                .map(|link| (link.clone(), String::new()))
+                .collect(),
+            None => self.get_course_video_links().await?,
+        };
+        let links = self.pending_links(links);
 
-        let video_config_script = video_page.select("/html/body/div[2]/div[4]/div/div/div[2]/div/section/div[2]/script")?
-            .as_node()
-            .ok_or(anyhow!("Could not find video config script tag on video playback site"))?
-            .text();
-
-        let video_config_script = video_config_script
-            .strip_prefix("//<![CDATA[\n")
-            .map(|rest| rest.strip_suffix("//]]>"))
-            .flatten()
-            .unwrap_or_else(|| {
-                tracing::warn!("Failed to remove CDATA wrapper from video config script");
-                &video_config_script
+        tracing::debug!(?links);
+        let (links, courses): (Vec<_>, Vec<_>) = links.into_iter().unzip();
+        let video_configs = self.discover(links).await?;
+        tracing::info!("Collected {} video configs, processing the videos", video_configs.len());
+
+        let mut report = RunReport {
+            videos: video_configs.len(),
+            ..RunReport::default()
+        };
+        let mut tasks = JoinSet::new();
+        let mut finished = BTreeMap::new();
+        for (index, ((link, video_config), course)) in video_configs.into_iter().zip(courses).enumerate() {
+            let video_config = match video_config {
+                Ok(video_config) => video_config,
+                Err(err) => {
+                    tracing::error!(link, ?err, "Failed to get video config");
+                    report.failed += 1;
+                    // leaves no gap in the order rows are passed on in
+                    finished.insert(index, None);
+                    continue;
+                }
+            };
+            let client = self.clone();
+            tasks.spawn(async move {
+                let result = match client.video_permits.clone().acquire_owned().await {
+                    Ok(_permit) => client.get_data(index, link.clone(), course, video_config).await,
+                    Err(err) => Err(err.into()),
+                };
+                (index, link, result)
             });
+        }
 
-        let video_config = video_config_script.strip_prefix("window.episode = ")
-            .ok_or(anyhow!("Failed to remove global setter from video config script"))?;
-        let video_config = json::parse(video_config)
-            .context("Failed to parse config json from video config script")?;
+        // videos without a config are done already
+        let mut done = report.failed;
+        let mut next = 0;
+        // rows are held back only until all videos before them are done, so they are passed on in
+        // link order while a slow video delays just the rows after it
+        while let Some(joined) = tasks.join_next().await {
+            let (index, link, result) = joined?;
+            finished.insert(index, Some((link, result)));
+            done += 1;
+            self.print_progress(done, report.videos);
 
-        Ok(video_config)
-    }
-    
-    fn get_caption_url(video_config: &JsonValue) -> Option<&str> {
-        let captions = if let JsonValue::Array(captions) = &video_config["captions"] {
-            captions
-        } else {
-            return None
-        };
-        
-        let caption = captions.iter()
-            .find(|caption| caption["format"].as_str() == Some("vtt") && caption["lang"] == "de")?;
-        
-        caption["url"].as_str()
+            while let Some(video) = finished.remove(&next) {
+                next += 1;
+                let Some((link, result)) = video else {
+                    continue;
+                };
+                Self::handle_result(link, result, &mut report, &mut on_row)?;
+            }
+        }
+
+        Ok(report)
     }
 
-    fn get_video_url(video_config: &JsonValue) -> Option<&str> {
-        let streams = if let JsonValue::Array(streams) = &video_config["streams"] {
-            streams
+    /// Pass on the row of a processed video, or record why it has none
+    fn handle_result(link: String, result: anyhow::Result<DataRow>, report: &mut RunReport, on_row: &mut impl FnMut(DataRow) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        match result {
+            Ok(result) => on_row(result)?,
+            Err(err) if matches!(err.downcast_ref(), Some(TranscriptError::SkipNoCaption)) => {
+                tracing::info!(link, "Skipped video without captions, run again with --only-captionless to transcribe it");
+                report.captionless.push(link);
+            }
+            Err(err) if matches!(err.downcast_ref(), Some(TranscriptError::SkipEpisode)) => {
+                tracing::info!(link, "Skipped episode listed in skip_episodes")
+            }
+            Err(err) if matches!(err.downcast_ref(), Some(TranscriptError::SkipTooLarge { .. })) => {
+                tracing::info!(link, "{err}");
+                report.skipped.push(link);
+            }
+            Err(err) => {
+                tracing::error!(?err);
+                report.failed += 1;
+            }
+        }
+        Ok(())
+    }
+    
+    /// The links of videos still to process in link order, without duplicates, the videos of a
+    /// resumed run and skipped episodes
+    fn pending_links(&self, mut links: Vec<(String, String)>) -> Vec<(String, String)> {
+        // process in an order independent of the page layout for reproducible results
+        links.sort();
+        // videos listed in several courses are processed once, for the first of them
+        links.dedup_by(|(link, _), (kept, _)| link == kept);
+        if !self.done_links.is_empty() {
+            let before = links.len();
+            links.retain(|(link, _)| !self.done_links.contains(link));
+            tracing::info!("Skipping {} videos processed by the resumed run", before - links.len());
+        }
+        links.retain(|(link, _)| match Self::link_episode_id(link) {
+            Some(episode_id) if self.skip_episodes.contains(&episode_id) => {
+                tracing::info!(link, "Skipping episode {episode_id}");
+                false
+            }
+            _ => true,
+        });
+        links
+    }
+
+    /// Report how many of the videos of the run are done, on a single updated line if stderr is a
+    /// terminal
+    fn print_progress(&self, done: usize, total: usize) {
+        tracing::info!("Processed {done}/{total} videos");
+        if !self.show_progress {
+            return;
+        }
+        if std::io::stderr().is_terminal() {
+            eprint!("\rProcessed {done}/{total} videos");
+            if done == total {
+                eprintln!();
+            }
         } else {
-            return None;
+            eprintln!("Processed {done}/{total} videos");
+        }
+    }
+
+    /// Process all recordings of a single course, yielding the rows in link order as they are done
+    ///
+    /// Videos that fail are logged and left out, the stream ends with an error if the course itself
+    /// could not be processed.
+    pub fn course_rows(&self, course_url: Url) -> impl futures::Stream<Item = anyhow::Result<DataRow>> {
+        let client = DefactoClient {
+            course_urls: vec![course_url],
+            captionless_links: None,
+            // the consumer of the stream decides what to show
+            show_progress: false,
+            ..self.clone()
         };
+        let (sender, receiver) = mpsc::unbounded_channel();
+        task::spawn(async move {
+            let rows = sender.clone();
+            let result = client.do_stuff(|row| rows.send(Ok(row))
+                .map_err(|_| anyhow!("Row stream was dropped"))).await;
+            if let Err(err) = result {
+                let _ = sender.send(Err(err));
+            }
+        });
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|row| (row, receiver))
+        })
+    }
 
-        streams.iter()
-            .find(|stream| stream["role"].as_str() == Some("mainAudio"))
-            .and_then(|stream| {
-                let mp4_streams = if let JsonValue::Array(mp4_streams) = &stream["sources"]["mp4"] {
-                    mp4_streams
-                } else {
-                    return None;
-                };
+    /// The episode id in the `e` query parameter of a playback link, if it has one
+    fn link_episode_id(link: &str) -> Option<String> {
+        let link = Url::parse(link).ok()?;
+        link.query_pairs()
+            .find(|(name, _)| name == "e")
+            .map(|(_, episode_id)| episode_id.into_owned())
+    }
 
-                mp4_streams.iter()
-                    .filter_map(|stream| {
-                        let src = stream["src"].as_str()?;
-                        let w = stream["res"]["w"].as_usize()?;
-                        let h = stream["res"]["h"].as_usize()?;
-                        Some((src, w * h))
-                    })
-                    .min_by(|(_, size_a), (_, size_b)| size_a.cmp(size_b))
-                    .map(|(src, _)| src)
+    /// Fetch the configs of all videos, at most `discovery_concurrency` at a time, keeping the
+    /// order of `links`
+    pub async fn discover(&self, links: Vec<String>) -> anyhow::Result<Vec<(String, anyhow::Result<EpisodeConfig>)>> {
+        let permits = Arc::new(Semaphore::new(self.discovery_concurrency.max(1)));
+        let handles = links.into_iter()
+            .map(|link| {
+                let client = self.clone();
+                let permits = permits.clone();
+                let task_link = link.clone();
+                (link, task::spawn(async move {
+                    let _permit = permits.acquire_owned().await?;
+                    tracing::info!(link = task_link, "Getting video config");
+                    client.get_video_config(task_link).await
+                }))
             })
+            .collect::<Vec<_>>();
+
+        let mut video_configs = Vec::with_capacity(handles.len());
+        for (link, handle) in handles {
+            video_configs.push((link, handle.await?));
+        }
+        Ok(video_configs)
     }
 
-    pub async fn get_transcript(&self, video_config: &JsonValue) -> anyhow::Result<String> {
-        let transcript = if let Some(caption_url) = Self::get_caption_url(video_config) {
-            self.get_opencast_transcript(caption_url).await
+    /// Fetch the configs of all videos and sort them into captioned and whisper work
+    pub async fn estimate(&self) -> anyhow::Result<Estimate> {
+        let mut links = self.get_course_video_links().await?
+            .into_iter()
+            .map(|(link, _)| link)
+            .collect::<Vec<_>>();
+        links.sort();
+        links.dedup();
+
+        let mut estimate = Estimate::default();
+        for (_, video_config) in self.discover(links).await? {
+            let video_config = match video_config {
+                Ok(video_config) => video_config,
+                Err(err) => {
+                    tracing::error!(?err);
+                    estimate.failed += 1;
+                    continue;
+                }
+            };
+
+            if self.get_caption_url(&video_config).is_some() {
+                estimate.captioned += 1;
+                continue;
+            }
+            estimate.whisper += 1;
+            // paella manifests state the duration in seconds
+            match video_config.metadata.duration {
+                Some(duration) => estimate.whisper_duration += Duration::from_secs_f64(duration),
+                None => estimate.unknown_duration += 1,
+            }
+        }
+
+        Ok(estimate)
+    }
+
+    /// Transcribe and count a single video with its previously fetched config
+    ///
+    /// All logs are emitted within a `video` span carrying the given `index`, the episode id and
+    /// the title, so lines of concurrently processed videos can be told apart.
+    pub async fn get_data(&self, index: usize, link: String, course: String, video_config: EpisodeConfig) -> anyhow::Result<DataRow> {
+        let span = span!(Level::INFO, "video", index, episode = field::Empty, title = field::Empty);
+
+        async {
+            let title = video_config.metadata.title.as_str();
+            let span = Span::current();
+            span.record("title", title);
+            if let Some(episode_id) = &video_config.metadata.id {
+                span.record("episode", episode_id.as_str());
+                // links without an episode id in them can only be skipped once the config is known
+                if self.skip_episodes.contains(episode_id) {
+                    return Err(TranscriptError::SkipEpisode.into());
+                }
+            }
+
+            let ((row, warnings), bytes) = bandwidth::collect(warnings::collect(self.process_video(link, title, &video_config))).await;
+            tracing::info!("Downloaded {} for this video", bandwidth::format_bytes(bytes));
+            row.map(|row| DataRow { course, warnings, ..row })
+        }
+            .instrument(span)
+            .await
+    }
+
+    async fn process_video(&self, link: String, title: &str, video_config: &EpisodeConfig) -> anyhow::Result<DataRow> {
+        let (timed_transcript, source) = self.get_sourced_transcript(&link, video_config).await?;
+        if let Some(compare_dir) = &self.compare {
+            if let Err(err) = self.compare_transcripts(compare_dir, video_config).await {
+                data_warning!("Failed to compare captions with whisper: {err:#}");
+            }
+        }
+
+        let row = DataRow { source, ..self.count_transcript(link, title, timed_transcript) };
+        #[cfg(feature = "ocr")]
+        let row = if self.slide_ocr {
+            match self.get_slide_counts(video_config).await {
+                Ok(slide_counts) => DataRow { slide_counts: Some(slide_counts), ..row },
+                Err(err) => {
+                    data_warning!("Failed to count matches on slides: {err:#}");
+                    row
+                }
+            }
         } else {
-            Err(anyhow!("Could not find a caption url"))
+            row
         };
-        
-        match transcript {
-            Ok(transcript) => Ok(transcript),
-            Err(err) => {
-                tracing::warn!("{err}");
-                
-                if let Some(video_url) = Self::get_video_url(video_config) {
-                    Ok(self.get_whisper_transcript(video_url).await)
-                } else {
-                    Err(anyhow!("Could not find a video url"))
-                }?
+
+        if let Some(clip_export) = &self.clip_export {
+            if !row.matches.is_empty() {
+                if let Err(err) = self.export_clips(clip_export, title, video_config, &row.matches).await {
+                    data_warning!("Failed to export clips: {err:#}");
+                }
             }
         }
+
+        Ok(row)
     }
 
-    pub async fn get_opencast_transcript(&self, caption_url: impl IntoUrl) -> anyhow::Result<String> {
-        tracing::info!("Downloading captions from: {}", caption_url.as_str());
-        let captions = self.client.get(caption_url)
-            .send().await?
+    /// Clean up a transcript and count its matches, the analysis shared by fresh and cached
+    /// transcripts
+    fn count_transcript(&self, link: String, title: &str, mut timed_transcript: Transcript) -> DataRow {
+        let substitutions = postprocess::apply(&self.post_processors, &mut timed_transcript);
+        if substitutions > 0 {
+            tracing::info!("Made {substitutions} replacements in the transcript");
+        }
+        let transcript = timed_transcript.text();
+        tracing::trace!(transcript);
+
+        let counts = count_patterns(&transcript);
+        let word_count = transcript.split_whitespace().count();
+        let duration_ms = timed_transcript.duration_ms();
+        if duration_ms >= MIN_REVIEW_DURATION_MS
+            && (word_count as f64) < MIN_WORDS_PER_MINUTE * (duration_ms as f64 / 60_000.0) {
+            data_warning!("Only {word_count} words over {} minutes, the transcript may be incomplete", duration_ms / 60_000);
+        }
+
+        let matches = Self::find_matches(&timed_transcript);
+        let sequence_counts = self.sequences.iter()
+            .map(|sequence| (sequence.name.clone(), count_sequence(&matches, sequence)))
+            .collect();
+
+        DataRow {
+            title: title.to_string(),
+            link,
+            course: String::new(),
+            transcript,
+            counts,
+            word_count,
+            duration_ms,
+            source: TranscriptSource::Unknown,
+            sequence_counts,
+            #[cfg(feature = "ocr")]
+            slide_counts: None,
+            matches,
+            segments: self.include_segments.then_some(timed_transcript.segments),
+            warnings: vec![],
+        }
+    }
+
+    /// Path of the cached transcript of a video, keyed by the SHA-256 of the url of the captions
+    /// chosen by the configured languages or, for videos without such captions, its video url
+    fn transcript_cache_path(&self, video_config: &EpisodeConfig) -> std::io::Result<PathBuf> {
+        let source = self.get_caption_url(video_config)
+            .or_else(|| self.get_video_url(video_config))
+            .map(|url| hex::encode(Sha256::digest(url.as_bytes())))
+            .unwrap_or_else(|| Self::file_stem(video_config));
+        Ok(self.transcripts_dir()?.join(format!("{source}.transcript.json")))
+    }
+
+    /// Read a transcript cached by an earlier run
+    fn read_cached_transcript(path: &Path) -> anyhow::Result<CachedTranscript> {
+        let cached = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&cached)?)
+    }
+
+    /// Keep the transcript as fetched, before any post processing, for later runs and `recount`
+    fn cache_transcript(path: &Path, cached: &CachedTranscript) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string(cached)?)?;
+        Ok(())
+    }
+
+    /// Count the matches in all cached transcripts again, without any requests or transcription,
+    /// handing every row to `on_row` in link order
+    pub async fn recount(&self, mut on_row: impl FnMut(DataRow) -> anyhow::Result<()>) -> anyhow::Result<RunReport> {
+        let mut paths = std::fs::read_dir(self.transcripts_dir()?)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.retain(|path| path.to_string_lossy().ends_with(".transcript.json"));
+
+        let mut report = RunReport::default();
+        let mut transcripts = Vec::with_capacity(paths.len());
+        for path in paths {
+            let cached = match Self::read_cached_transcript(&path) {
+                Ok(cached) => cached,
+                Err(err) => {
+                    tracing::error!("Failed to read cached transcript {}: {err:#}", path.display());
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            if Self::link_episode_id(&cached.link).is_some_and(|episode_id| self.skip_episodes.contains(&episode_id)) {
+                tracing::info!(link = cached.link, "Skipped episode listed in skip_episodes");
+                continue;
+            }
+            transcripts.push(cached);
+        }
+        // same order as a full run over the course
+        transcripts.sort_by(|a, b| a.link.cmp(&b.link));
+        report.videos = report.failed + transcripts.len();
+
+        for (index, CachedTranscript { title, link, source, transcript }) in transcripts.into_iter().enumerate() {
+            let span = span!(Level::INFO, "video", index, title);
+            let (row, warnings) = warnings::collect(async { self.count_transcript(link, &title, transcript) })
+                .instrument(span)
+                .await;
+            on_row(DataRow { source, warnings, ..row })?;
+        }
+        Ok(report)
+    }
+
+    /// Write a word diff between the captions and the whisper transcript of a video, both cleaned
+    /// up by the post processors so only disagreements the replacements do not fix yet remain
+    async fn compare_transcripts(&self, compare_dir: &Path, video_config: &EpisodeConfig) -> anyhow::Result<()> {
+        if self.get_caption_url(video_config).is_none() {
+            return Ok(());
+        }
+        let mut captions = self.get_caption_transcript(video_config).await?;
+        postprocess::apply(&self.post_processors, &mut captions);
+        let mut whisper = self.get_audio_transcript(video_config).await?;
+        postprocess::apply(&self.post_processors, &mut whisper);
+
+        let diff_path = compare_dir.join(format!("{}.diff", Self::file_stem(video_config)));
+        tracing::info!("Writing transcript diff to {}", diff_path.display());
+        std::fs::write(&diff_path, transcript::word_diff(&captions.text(), &whisper.text()))?;
+        Ok(())
+    }
+
+    /// Name for files exported per video, the episode id or else the slugified title
+    fn file_stem(video_config: &EpisodeConfig) -> String {
+        match &video_config.metadata.id {
+            Some(episode_id) => episode_id.clone(),
+            None => clip::slugify(&video_config.metadata.title),
+        }
+    }
+
+    /// Locate every pattern match together with the time span of the segment it occurred in
+    pub fn find_matches(transcript: &Transcript) -> Vec<Match> {
+        let mut matches = vec![];
+        for segment in &transcript.segments {
+            let text = MatchText::new(&segment.text);
+            for pattern in matching_patterns(&text) {
+                matches.extend(pattern.find_ranges_in(&text)
+                    .into_iter()
+                    .map(|range| Match {
+                        pattern: pattern.name.clone(),
+                        start_ms: segment.start_ms,
+                        end_ms: segment.end_ms,
+                        text: segment.text[range].trim().to_string(),
+                        snippet: segment.text.clone(),
+                    }));
+            }
+        }
+        matches
+    }
+
+    pub async fn export_clips(&self, clip_export: &ClipExport, title: &str, video_config: &EpisodeConfig, matches: &[Match]) -> anyhow::Result<()> {
+        let video_url = self.find_video_url(video_config).await?;
+        // with transcode_first the video is gone after transcribing, the mp3 of its audio is kept
+        let media_path = if self.transcode_first {
+            self.download_audio(video_url.parse()?).await?
+        } else {
+            self.download_video(video_url).await?
+        };
+        let audio_data = STTContext::get_audio_data_blocking(&media_path, AudioFormat::default()).await?;
+
+        let slug = clip::slugify(title);
+        let padding = clip_export.padding.as_millis() as u64;
+        let mut indices = HashMap::new();
+        let clips = matches.iter()
+            .map(|found| {
+                let index = indices.entry(found.pattern.as_str()).or_insert(0usize);
+                *index += 1;
+                let clip_path = clip_export.dir.join(format!("{slug}-{}-{index}.mp3", clip::slugify(&found.pattern)));
+                (found.start_ms.saturating_sub(padding)..found.end_ms + padding, clip_path)
+            })
+            .collect::<Vec<_>>();
+
+        // encoding is CPU bound like decoding and must not stall a runtime worker either
+        run_blocking(move || {
+            for (range, clip_path) in clips {
+                tracing::info!("Exporting clip {}", clip_path.display());
+                clip::export_clip(&audio_data, WHISPER_SAMPLE_RATE, range, &clip_path)?;
+            }
+            Ok(())
+        }).await
+    }
+
+    /// Send a GET request, retrying it up to `max_retries` times after connection errors and
+    /// server errors
+    ///
+    /// Other error statuses are returned right away, the response of the last attempt is
+    /// returned as is once the retries are used up.
+    async fn get(&self, url: impl IntoUrl) -> anyhow::Result<Response> {
+        self.get_with_timeout(url, None).await
+    }
+
+    /// Send a GET request like [`Self::get`], with a time limit other than the one of the session
+    async fn get_with_timeout(&self, url: impl IntoUrl, timeout: Option<Duration>) -> anyhow::Result<Response> {
+        let url = url.into_url()?;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url.clone());
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            let result = request.send().await;
+            let transient = match &result {
+                Ok(response) => retry::is_transient_status(response.status()),
+                Err(err) => retry::is_transient_error(err),
+            };
+            if !transient || attempt >= self.max_retries {
+                return Ok(result?);
+            }
+
+            let delay = retry::backoff(attempt);
+            match &result {
+                Ok(response) => tracing::warn!("{url} responded with {}, retrying in {delay:.1?}", response.status()),
+                Err(err) => tracing::warn!("Request to {url} failed, retrying in {delay:.1?}: {err}"),
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Load a page from the snapshot if one is configured or request it otherwise
+    async fn get_page(&self, link: impl IntoUrl) -> anyhow::Result<XHtml> {
+        let link = link.into_url()?;
+        if let Some(snapshot) = &self.snapshot {
+            tracing::debug!("Loading {link} from snapshot");
+            return Ok(XHtml::new(snapshot.get(&link)?)?);
+        }
+
+        let page = self.get(link).await?
+            .error_for_status()?
             .text().await?;
-        let captions = WebVtt::parse(&captions)
-            .context("Failed to parse vtt from caption file")?;
+        bandwidth::record(page.len() as u64);
+        Ok(XHtml::new(page)?)
+    }
 
-        if captions.blocks.len() == 0 {
-            return Err(anyhow!("Captions are empty"))
+    /// Collect the playback links of the recordings of all configured courses, each paired with
+    /// the course page it was listed on
+    pub async fn get_course_video_links(&self) -> anyhow::Result<Vec<(String, String)>> {
+        if self.course_urls.is_empty() {
+            return Err(anyhow!("No course to process, set course_urls or pass its Opencast page with --course-url"));
         }
+        let mut links = vec![];
+        for course_url in &self.course_urls {
+            let course_links = self.get_video_links(course_url.clone()).await?;
+            tracing::info!(course = course_url.as_str(), "Found {} recordings", course_links.len());
+            links.extend(course_links.into_iter().map(|link| (link, course_url.to_string())));
+        }
+        Ok(links)
+    }
 
-        let raw_transcript = captions.blocks.into_iter()
-            .filter_map(|block| if let VttBlock::Que(cue) = block {
-                Some(cue)
+    /// Collect the playback links of all recordings in the course's Opencast table
+    ///
+    /// Tables split into pages are followed through the links of their pagination controls and any
+    /// `rel="next"` link until every page was visited.
+    pub async fn get_video_links(&self, link: impl IntoUrl) -> anyhow::Result<Vec<String>> {
+        let link = link.into_url()?;
+        let mut pages = VecDeque::from([link.clone()]);
+        let mut visited = HashSet::from([link.clone()]);
+        let mut links = vec![];
+        while let Some(page_url) = pages.pop_front() {
+            let recordings = self.get_page(page_url.clone()).await?;
+            links.extend(Self::get_page_video_links(&recordings, &link)?);
+
+            for next_url in Self::get_page_urls(&recordings, &page_url)? {
+                if visited.insert(next_url.clone()) {
+                    tracing::debug!("Following recording list page {next_url}");
+                    pages.push_back(next_url);
+                }
+            }
+        }
+        // the first page is usually linked again under an explicit page number
+        let mut seen = HashSet::new();
+        links.retain(|link| seen.insert(link.clone()));
+        if visited.len() > 1 {
+            tracing::info!("Collected {} recordings from {} pages", links.len(), visited.len());
+        }
+
+        Ok(links)
+    }
+
+    /// Urls of the pages of the same recording list linked from the pagination controls of a page
+    fn get_page_urls(recordings: &XHtml, page_url: &Url) -> anyhow::Result<Vec<Url>> {
+        let page_links = recordings.select("//*[contains(@class, 'pagination')]//a | //a[@rel='next']")?
+            .as_nodes();
+        Ok(page_links.iter()
+            .filter_map(|node| node.attr("href"))
+            .filter_map(|href| page_url.join(&href).ok())
+            .filter(|next_url| next_url.path() == page_url.path())
+            .collect())
+    }
+
+    /// Collect the playback links of the recordings on a single page of the Opencast table
+    ///
+    /// Rows without an `<a href>` are checked for a `data-href` attribute, and as a last resort a
+    /// `data-episode-id` attribute from which the playback url is reconstructed.
+    fn get_page_video_links(recordings: &XHtml, link: &Url) -> anyhow::Result<Vec<String>> {
+        let mut table = None;
+        for selector in RECORDING_TABLE_SELECTORS {
+            table = recordings.select(selector)?.as_node();
+            if table.is_some() {
+                break;
+            }
+        }
+        let Some(table) = table else {
+            Self::log_page_snippet(recordings);
+            return Err(anyhow!("Could not find the recording table on {link}"));
+        };
+        let mut links = vec![];
+        for row in table.findnodes("tr")? {
+            let hrefs = row.findnodes("td/a")?
+                .iter()
+                .filter_map(|node| node.attr("href"))
+                .collect::<Vec<_>>();
+            if !hrefs.is_empty() {
+                links.extend(hrefs);
+            } else if let Some(href) = row.findvalue(".//@data-href")? {
+                links.push(link.join(&href)?.to_string());
+            } else if let Some(episode_id) = row.findvalue(".//@data-episode-id")? {
+                let mut episode_url = link.clone();
+                episode_url.query_pairs_mut().append_pair("e", &episode_id);
+                links.push(episode_url.to_string());
             } else {
-                None
+                tracing::debug!("No playback link in recording table row");
+            }
+        }
+
+        Ok(links)
+    }
+
+    pub async fn get_video_config(&self, link: impl IntoUrl) -> anyhow::Result<EpisodeConfig> {
+        let video_page = self.get_page(link).await?;
+
+        if let Some(video_config_pattern) = &self.video_config_pattern {
+            return Self::get_pattern_config(&video_page, video_config_pattern);
+        }
+
+        let video_config_script = video_page.select("//script[contains(text(), 'window.episode')]")?
+            .as_node()
+            .map(|script| script.text());
+
+        let Some(video_config_script) = video_config_script else {
+            return Self::get_json_script_config(&video_page);
+        };
+
+        let video_config = Self::extract_episode_json(&video_config_script)?;
+        EpisodeConfig::parse(video_config)
+            .context("Failed to parse config json from video config script")
+    }
+
+    /// The JSON object assigned to `window.episode` in the video config script
+    ///
+    /// The object ends where its braces balance, so statements after the assignment are left out.
+    fn extract_episode_json(script: &str) -> anyhow::Result<&str> {
+        let assignment = EPISODE_ASSIGNMENT.get_or_init(|| {
+            Regex::new(r"window\.episode\s*=\s*\{").expect("Episode assignment pattern is valid")
+        });
+        let script = script.trim();
+        let missing = || anyhow!(
+            "Failed to find the object assigned to window.episode in video config script: {}",
+            output::truncate_field(script, SCRIPT_SNIPPET_CHARS),
+        );
+        let assigned = assignment.find(script).ok_or_else(missing)?;
+        // starting at the opening brace
+        let video_config = &script[assigned.end() - 1..];
+        let mut objects = serde_json::Deserializer::from_str(video_config).into_iter::<IgnoredAny>();
+        match objects.next() {
+            Some(Ok(_)) => Ok(&video_config[..objects.byte_offset()]),
+            Some(Err(err)) => Err(err).with_context(|| format!(
+                "Failed to parse the object assigned to window.episode in video config script: {}",
+                output::truncate_field(script, SCRIPT_SNIPPET_CHARS),
+            )),
+            None => Err(missing()),
+        }
+    }
+
+    /// Log the start of the main content of a page at debug level, to see how its layout
+    /// changed when an expected element is missing
+    fn log_page_snippet(page: &XHtml) {
+        if !tracing::enabled!(Level::DEBUG) {
+            return;
+        }
+        let main = page.select("//*[@id='region-main']").ok().and_then(|main| main.as_node());
+        let snippet = main.or_else(|| page.select("//body").ok().and_then(|body| body.as_node()))
+            .map(|node| node.html())
+            .unwrap_or_default();
+        tracing::debug!(snippet = %output::truncate_field(&snippet, PAGE_SNIPPET_CHARS), "Page content");
+    }
+
+    /// Newer players embed the episode as a `<script type="application/json">` block instead of
+    /// assigning it to `window.episode`
+    fn get_json_script_config(video_page: &XHtml) -> anyhow::Result<EpisodeConfig> {
+        let scripts = video_page.select("//script[@type='application/json']")?
+            .as_nodes();
+        let video_config = scripts.iter()
+            .map(|script| script.text())
+            .find(|script| serde_json::from_str::<serde_json::Value>(script)
+                .is_ok_and(|config| config.get("streams").is_some() || config.get("metadata").is_some()))
+            .ok_or_else(|| {
+                Self::log_page_snippet(video_page);
+                anyhow!("Could not find video config script tag on video playback site")
+            })?;
+        EpisodeConfig::parse(&video_config)
+            .context("Failed to parse config json from video config script tag")
+    }
+
+    /// Extract the episode from the first script the configured pattern captures valid JSON from
+    fn get_pattern_config(video_page: &XHtml, video_config_pattern: &Regex) -> anyhow::Result<EpisodeConfig> {
+        let scripts = video_page.select("//script")?
+            .as_nodes();
+        let video_config = scripts.iter()
+            .find_map(|script| {
+                let script = script.text();
+                let video_config = video_config_pattern.captures(&script)?.get(1)?;
+                Some(video_config.as_str().to_string())
             })
-            .map(|cue| cue.payload.join(" "))
+            .ok_or(anyhow!("No video page script matched the configured video config pattern"))?;
+        EpisodeConfig::parse(&video_config)
+            .context("Failed to parse config json captured by the video config pattern")
+    }
+
+    /// The captions to use, in the most preferred language that has any
+    ///
+    /// This runs several times per video, so a fallback to another language is only reported by
+    /// [`Self::warn_language_fallback`] once the captions are actually used.
+    fn get_caption<'a>(&self, video_config: &'a EpisodeConfig) -> Option<&'a Caption> {
+        // SRT only if there is no VTT in the same language, it carries no more than the cue text
+        let mut captions = video_config.captions.iter()
+            .filter_map(|caption| match caption.format.as_str() {
+                "vtt" | "json" => Some((0, caption)),
+                "srt" => Some((1, caption)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        captions.sort_by_key(|(rank, _)| *rank);
+        let captions = captions.into_iter()
+            .map(|(_, caption)| caption)
+            .collect::<Vec<_>>();
+        self.caption_languages.iter()
+            .find_map(|lang| captions.iter().find(|caption| caption.lang == *lang))
+            .or_else(|| captions.first().filter(|_| self.allow_any_language))
+            .copied()
+    }
+
+    fn get_caption_url<'a>(&self, video_config: &'a EpisodeConfig) -> Option<&'a str> {
+        self.get_caption(video_config).map(|caption| caption.url.as_str())
+    }
+
+    /// Record a warning if the captions of the video are in none of the preferred languages
+    fn warn_language_fallback(&self, video_config: &EpisodeConfig) {
+        if let Some(caption) = self.get_caption(video_config).filter(|caption| !self.caption_languages.contains(&caption.lang)) {
+            data_warning!("No captions in {:?} found, fell back to language {}", self.caption_languages, caption.lang);
+        }
+    }
+
+    fn get_video_url<'a>(&self, video_config: &'a EpisodeConfig) -> Option<&'a str> {
+        video_config.streams.iter()
+            .find(|stream| stream.role.as_deref() == Some("mainAudio"))
+            .and_then(|stream| self.get_audio_source(stream))
+    }
+
+    /// The source of an Opencast stream to transcribe, as chosen by the audio stream preference
+    fn get_audio_source<'a>(&self, stream: &'a Stream) -> Option<&'a str> {
+        let largest = || Self::get_sized_source(stream, true);
+        match self.audio_stream_preference {
+            AudioStreamPreference::Smallest => Self::get_stream_source(stream),
+            AudioStreamPreference::Largest => largest(),
+            AudioStreamPreference::BestAudio => stream.sources.audio.first()
+                .map(|source| source.src.as_str())
+                .or_else(largest),
+        }
+    }
+
+    /// The smallest mp4 source of an Opencast stream
+    fn get_stream_source(stream: &Stream) -> Option<&str> {
+        Self::get_sized_source(stream, false)
+    }
+
+    /// The largest or smallest mp4 source of an Opencast stream
+    ///
+    /// Sources without a resolution are skipped, unless none of the sources has one.
+    fn get_sized_source(stream: &Stream, largest: bool) -> Option<&str> {
+        let sized = stream.sources.mp4.iter()
+            .filter_map(|source| Some((source.pixels()?, source)));
+        let source = if largest {
+            sized.max_by_key(|(pixels, _)| *pixels)
+        } else {
+            sized.min_by_key(|(pixels, _)| *pixels)
+        };
+        source.map(|(_, source)| source)
+            .or_else(|| stream.sources.mp4.first())
+            .map(|source| source.src.as_str())
+    }
+
+    /// Find the url of a video carrying the lecture audio
+    ///
+    /// Prefers the stream marked as `mainAudio`. Recordings without that role (e.g. separate
+    /// presenter and slide streams) are probed with ffmpeg for an audio track, presenter first.
+    pub async fn find_video_url(&self, video_config: &EpisodeConfig) -> anyhow::Result<String> {
+        if let Some(video_url) = self.get_video_url(video_config) {
+            return Ok(video_url.to_string());
+        }
+
+        let mut candidates = video_config.streams.iter()
+            .filter_map(|stream| Some((stream.content.as_deref(), self.get_audio_source(stream)?.to_string())))
             .collect::<Vec<_>>();
+        candidates.sort_by_key(|(content, _)| *content != Some("presenter"));
 
-        let mut transcript = Vec::with_capacity(raw_transcript.len());
-        let mut last_block = raw_transcript.first().unwrap().trim().to_string();
-        transcript.push(last_block.clone());
-        for block in raw_transcript {
-            let block = block.trim().to_string();
-            if block != last_block {
-                transcript.push(block.clone());
-                last_block = block;
+        for (content, video_url) in candidates {
+            tracing::debug!("Probing {} stream for audio: {video_url}", content.unwrap_or("unknown"));
+            let probe_url = video_url.clone();
+            let has_audio = task::spawn_blocking(move || -> anyhow::Result<bool> {
+                let ictx = input(&probe_url)?;
+                let has_audio = ictx.streams().best(Type::Audio).is_some();
+                Ok(has_audio)
+            }).await?;
+            match has_audio {
+                Ok(true) => {
+                    data_warning!("No mainAudio stream, using {} stream", content.unwrap_or("unknown"));
+                    return Ok(video_url);
+                }
+                Ok(false) => {}
+                Err(err) => tracing::debug!("Failed to probe {video_url}: {err:#}"),
             }
         }
 
-        Ok(transcript.join(" "))
+        Err(anyhow!("Could not find a video url with an audio track"))
+    }
+
+    /// Get the best available transcript of the video at `link` with the given config, reusing
+    /// the transcript of an earlier run unless the transcript cache is disabled
+    pub async fn get_transcript(&self, link: &str, video_config: &EpisodeConfig) -> anyhow::Result<Transcript> {
+        Ok(self.get_sourced_transcript(link, video_config).await?.0)
+    }
+
+    /// Get the transcript like [`Self::get_transcript`], together with where it came from
+    async fn get_sourced_transcript(&self, link: &str, video_config: &EpisodeConfig) -> anyhow::Result<(Transcript, TranscriptSource)> {
+        let cache_path = self.transcript_cache_path(video_config)?;
+        if !self.no_transcript_cache && cache_path.exists() {
+            match Self::read_cached_transcript(&cache_path) {
+                Ok(cached) => {
+                    tracing::info!("Using cached transcript {}", cache_path.display());
+                    return Ok((cached.transcript, cached.source));
+                }
+                Err(err) => tracing::warn!("Failed to read cached transcript {}: {err:#}", cache_path.display()),
+            }
+        }
+
+        let (transcript, source) = self.fetch_transcript(link, video_config).await?;
+        let cached = CachedTranscript {
+            title: video_config.metadata.title.clone(),
+            link: link.to_string(),
+            source,
+            transcript,
+        };
+        if let Err(err) = Self::cache_transcript(&cache_path, &cached) {
+            tracing::warn!("Failed to cache transcript: {err:#}");
+        }
+        Ok((cached.transcript, source))
+    }
+
+    /// Fetch the best available transcript, preferring attached transcripts, then the search api,
+    /// then captions and finally whisper
+    async fn fetch_transcript(&self, link: &str, video_config: &EpisodeConfig) -> anyhow::Result<(Transcript, TranscriptSource)> {
+        if let Some(attachment) = video_config.attachments.iter().find(|attachment| attachment.is_transcript()) {
+            match self.get_attachment_transcript(attachment).await {
+                Ok(transcript) => return Ok((transcript, TranscriptSource::Attachment)),
+                Err(err) => data_warning!("Failed to get transcript from attachment: {err:#}"),
+            }
+        }
+
+        if self.captions_only {
+            if self.get_caption_url(video_config).is_none() {
+                return Err(TranscriptError::SkipNoCaption.into());
+            }
+            let transcript = self.get_current_caption_transcript(link, video_config).await?;
+            return Ok((transcript, TranscriptSource::Captions));
+        }
+
+        if let Some(search_api_url) = &self.search_api_url {
+            let episode_id = video_config.metadata.id.as_deref()
+                .ok_or(anyhow!("Could not find episode id in video metadata"));
+            let transcript = match episode_id {
+                Ok(episode_id) => self.get_search_transcript(search_api_url, episode_id).await,
+                Err(err) => Err(err),
+            };
+
+            match transcript {
+                Ok(transcript) => return Ok((transcript, TranscriptSource::Search)),
+                Err(err) => data_warning!("Failed to get transcript from search api: {err:#}"),
+            }
+        }
+
+        match self.get_current_caption_transcript(link, video_config).await {
+            Ok(transcript) => Ok((transcript, TranscriptSource::Captions)),
+            Err(err) => {
+                let fallback = match self.fallback_policy {
+                    FallbackPolicy::Always => true,
+                    FallbackPolicy::Never => false,
+                    FallbackPolicy::AskPerVideo => {
+                        let title = &video_config.metadata.title;
+                        ask_for_fallback(format!("No captions for \"{title}\" ({err}). Transcribe with whisper? [y/N] ")).await?
+                    }
+                };
+                if !fallback {
+                    return Err(err.context("Captions unavailable and whisper fallback declined"));
+                }
+
+                data_warning!("{err}, fell back to whisper");
+                let transcript = self.get_audio_transcript(video_config).await?;
+                Ok((transcript, TranscriptSource::Whisper))
+            }
+        }
+    }
+
+    /// Get the transcript from the published captions only
+    pub async fn get_caption_transcript(&self, video_config: &EpisodeConfig) -> anyhow::Result<Transcript> {
+        let caption_url = self.get_caption_url(video_config)
+            .ok_or(anyhow!("Could not find a caption url"))?;
+        self.get_opencast_transcript(caption_url).await
+    }
+
+    /// Get the transcript from the published captions, refetching the video config once if the
+    /// caption file is gone in case the config was stale and now points to different captions
+    async fn get_current_caption_transcript(&self, link: &str, video_config: &EpisodeConfig) -> anyhow::Result<Transcript> {
+        let err = match self.get_caption_transcript(video_config).await {
+            Ok(transcript) => {
+                self.warn_language_fallback(video_config);
+                return Ok(transcript);
+            }
+            Err(err) => err,
+        };
+        let not_found = err.downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status) == Some(StatusCode::NOT_FOUND);
+        if !not_found {
+            return Err(err);
+        }
+
+        tracing::info!("Captions not found, refetching the video config");
+        let fresh_config = self.get_video_config(link).await?;
+        match self.get_caption_url(&fresh_config) {
+            Some(caption_url) if Some(caption_url) != self.get_caption_url(video_config) => {
+                let transcript = self.get_opencast_transcript(caption_url).await?;
+                self.warn_language_fallback(&fresh_config);
+                Ok(transcript)
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// Get the transcript by running whisper on the recording's audio
+    pub async fn get_audio_transcript(&self, video_config: &EpisodeConfig) -> anyhow::Result<Transcript> {
+        let video_url = self.find_video_url(video_config).await?;
+        let resume_path = if self.resume_whisper {
+            let episode_id = video_config.metadata.id.as_deref()
+                .ok_or(anyhow!("Could not find episode id to resume whisper transcription"))?;
+            Some(self.transcripts_dir()?.join(format!("{episode_id}.jsonl")))
+        } else {
+            None
+        };
+        let transcript = self.get_whisper_transcript(video_url, resume_path).await?;
+
+        if let Some(vtt_export) = &self.vtt_export {
+            let vtt_path = vtt_export.join(format!("{}.vtt", Self::file_stem(video_config)));
+            tracing::info!("Writing whisper captions to {}", vtt_path.display());
+            if let Err(err) = std::fs::write(&vtt_path, transcript.to_vtt()) {
+                data_warning!("Failed to write whisper captions to {}: {err}", vtt_path.display());
+            }
+        }
+
+        Ok(transcript)
+    }
+
+    /// Get the transcript from a text or PDF transcript attached to the episode
+    ///
+    /// Attached documents carry no timing, so the transcript consists of a single segment.
+    pub async fn get_attachment_transcript(&self, attachment: &Attachment) -> anyhow::Result<Transcript> {
+        tracing::info!("Downloading transcript attachment from: {}", attachment.url);
+        let document = self.get(&attachment.url).await?
+            .error_for_status()?
+            .bytes().await?;
+        bandwidth::record(document.len() as u64);
+
+        let text = if attachment.is_pdf() {
+            task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&document)).await?
+                .context("Failed to extract text from transcript PDF")?
+        } else {
+            String::from_utf8(document.to_vec()).context("Transcript attachment is not UTF-8")?
+        };
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            return Err(anyhow!("Transcript attachment is empty"));
+        }
+
+        Ok(Transcript {
+            segments: vec![Segment {
+                start_ms: 0,
+                end_ms: 0,
+                text,
+            }],
+        })
+    }
+
+    /// Reconstruct a transcript from the segment texts indexed by the Opencast search service
+    pub async fn get_search_transcript(&self, search_api_url: &Url, episode_id: &str) -> anyhow::Result<Transcript> {
+        let mut url = search_api_url.join("episode.json")?;
+        url.query_pairs_mut().append_pair("id", episode_id);
+        tracing::info!("Fetching indexed segments from: {}", url);
+
+        let response = self.get(url).await?
+            .error_for_status()?
+            .text().await?;
+        bandwidth::record(response.len() as u64);
+        let response = json::parse(&response)
+            .context("Failed to parse search api response json")?;
+
+        // single results are not wrapped in an array by the search service
+        let segments = match &response["search-results"]["result"]["segments"]["segment"] {
+            JsonValue::Array(segments) => segments.iter().collect::<Vec<_>>(),
+            segment @ JsonValue::Object(_) => vec![segment],
+            _ => return Err(anyhow!("Could not find segments in search api response")),
+        };
+
+        let segments = segments.into_iter()
+            .filter_map(|segment| {
+                let text = segment["text"].as_str()?.trim();
+                if text.is_empty() {
+                    return None;
+                }
+                let start_ms = segment["time"].as_u64().unwrap_or_default();
+                Some(Segment {
+                    start_ms,
+                    end_ms: start_ms + segment["duration"].as_u64().unwrap_or_default(),
+                    text: text.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if segments.is_empty() {
+            return Err(anyhow!("Search api returned no segment text"));
+        }
+
+        Ok(Transcript { segments })
+    }
+
+    pub async fn get_opencast_transcript(&self, caption_url: impl IntoUrl) -> anyhow::Result<Transcript> {
+        tracing::info!("Downloading captions from: {}", caption_url.as_str());
+        let response = self.get(caption_url).await?
+            .error_for_status()?;
+        let is_json_type = response.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.contains("json"));
+        let captions = response.text().await?;
+        bandwidth::record(captions.len() as u64);
+
+        // some deployments publish the text extraction JSON instead of VTT, under any content type
+        if is_json_type || captions.trim_start().starts_with(['{', '[']) {
+            let transcript = Transcript::from_json_captions(&captions)
+                .context("Failed to parse json caption file")?;
+            if transcript.segments.is_empty() {
+                return Err(anyhow!("Captions are empty"))
+            }
+            return Ok(transcript);
+        }
+
+        Self::parse_subtitles(&captions)
+    }
+
+    /// Parse VTT or SRT captions, merging consecutive identical cues
+    fn parse_subtitles(captions: &str) -> anyhow::Result<Transcript> {
+        // the parsers reject a byte order mark
+        let captions = captions.trim_start_matches('\u{feff}');
+        // SRT files lack the WEBVTT header every VTT file starts with
+        let cues = if captions.trim_start().starts_with("WEBVTT") {
+            WebVtt::parse(captions)
+                .context("Failed to parse vtt from caption file")?
+                .blocks.into_iter()
+                .filter_map(|block| if let VttBlock::Que(cue) = block {
+                    Some((cue.timings.start.into(), cue.timings.end.into(), cue.payload))
+                } else {
+                    None
+                })
+                .collect::<Vec<(Duration, Duration, _)>>()
+        } else {
+            SubRip::parse(captions)
+                .context("Failed to parse srt from caption file")?
+                .subtitles.into_iter()
+                .map(|subtitle| (subtitle.start.into(), subtitle.end.into(), subtitle.text))
+                .collect()
+        };
+
+        if cues.is_empty() {
+            return Err(anyhow!("Captions are empty"))
+        }
+
+        let mut transcript = Transcript::default();
+        for (start, end, lines) in cues {
+            transcript.push_cue(start.as_millis() as u64, end.as_millis() as u64, transcript::join_lines(&lines));
+        }
+
+        Ok(transcript)
+    }
+
+    /// Count pattern matches in the OCR text of the recording's slide stream
+    #[cfg(feature = "ocr")]
+    pub async fn get_slide_counts(&self, video_config: &EpisodeConfig) -> anyhow::Result<IndexMap<String, usize>> {
+        let slides_url = video_config.streams.iter()
+            .find(|stream| stream.content.as_deref() == Some("presentation"))
+            .and_then(Self::get_stream_source)
+            .ok_or(anyhow!("Could not find a presentation stream"))?
+            .to_string();
+        let video_path = self.download_video(slides_url).await?;
+
+        let texts = run_blocking(move || ocr::slide_texts(video_path)).await?;
+        tracing::debug!("Recognized {} distinct slides", texts.len());
+        Ok(count_patterns(&texts.join(" ")))
+    }
+
+    /// Download a video into the cache, reusing a previous download if present
+    pub async fn download_video(&self, video_url: impl IntoUrl) -> anyhow::Result<PathBuf> {
+        let video_url = video_url.into_url()?;
+        let video_path = self.videos_dir()?.join(
+            Path::new(video_url.path())
+                .file_name()
+                .ok_or(anyhow!("No video file name"))?
+                .to_owned());
+        if video_path.exists() {
+            tracing::debug!("Using cached video {}", video_path.display());
+            return Ok(video_path);
+        }
+
+        tracing::info!("Downloading video from: {}", &video_url);
+        let mut response = self.get_with_timeout(video_url, Some(self.download_timeout)).await?;
+        if let Some(video_size) = response.content_length() {
+            check_video_size(video_size, self.max_video_size)?;
+        }
+        // written in chunks as they arrive and only moved into place once complete, so neither the
+        // whole video is held in memory nor an interrupted download taken for a cached video
+        let partial_path = video_path.with_extension("part");
+        let mut video_file = File::create(&partial_path)?;
+        let mut video_size = 0;
+        while let Some(chunk) = response.chunk().await? {
+            bandwidth::record(chunk.len() as u64);
+            if let Some(throttle) = &self.download_throttle {
+                throttle.consume(chunk.len() as u64).await;
+            }
+            video_size += chunk.len() as u64;
+            if let Err(err) = check_video_size(video_size, self.max_video_size) {
+                drop(video_file);
+                std::fs::remove_file(&partial_path)?;
+                return Err(err.into());
+            }
+            video_file.write_all(&chunk)?;
+        }
+        video_file.flush()?;
+        std::fs::rename(&partial_path, &video_path)?;
+        Ok(video_path)
     }
     
-    pub async fn get_whisper_transcript(&self, video_url: impl IntoUrl) -> anyhow::Result<String> {
+    /// Skip a video that is streamed instead of downloaded if the size its server states exceeds
+    /// `max_video_size`
+    async fn check_streamed_video_size(&self, video_url: &Url) -> anyhow::Result<()> {
+        if self.max_video_size.is_none() {
+            return Ok(());
+        }
+        let response = self.client.head(video_url.clone())
+            .timeout(self.download_timeout)
+            .send().await?
+            .error_for_status()?;
+        // the body of a HEAD response is empty, so only the header has the size
+        let video_size = response.headers().get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok());
+        if let Some(video_size) = video_size {
+            check_video_size(video_size, self.max_video_size)?;
+        }
+        Ok(())
+    }
+
+    /// Download a video and replace it with a small mp3 of its audio track, reusing a previous
+    /// transcode if present
+    pub async fn download_audio(&self, video_url: Url) -> anyhow::Result<PathBuf> {
+        let audio_path = self.audio_dir()?
+            .join(Path::new(video_url.path()).file_name().ok_or(anyhow!("No video file name"))?)
+            .with_extension("mp3");
+        if audio_path.exists() {
+            tracing::debug!("Using cached audio {}", audio_path.display());
+            return Ok(audio_path);
+        }
+
+        let video_path = self.download_video(video_url).await?;
+        tracing::info!("Transcoding {} to {}", video_path.display(), audio_path.display());
+        Self::transcode_to_mp3(&video_path, &audio_path).await?;
+        tokio::fs::remove_file(&video_path).await?;
+        Ok(audio_path)
+    }
+
+    /// Encode the audio track of a video as mono mp3 at the whisper sample rate
+    async fn transcode_to_mp3(video_path: &Path, audio_path: &Path) -> anyhow::Result<()> {
+        let audio_data = STTContext::get_audio_data_blocking(video_path, AudioFormat::default()).await?;
+        let audio_path = audio_path.to_path_buf();
+        let duration_ms = audio_data.len() as u64 * 1000 / WHISPER_SAMPLE_RATE as u64;
+        run_blocking(move || clip::export_clip(&audio_data, WHISPER_SAMPLE_RATE, 0..duration_ms, audio_path)).await
+    }
+
+    /// Transcribe a video with whisper, keeping progress in `resume_path` if given
+    pub async fn get_whisper_transcript(&self, video_url: impl IntoUrl, resume_path: Option<PathBuf>) -> anyhow::Result<Transcript> {
         let video_url = video_url.into_url()?;
-        tracing::info!("Downloading video to parse captions from: {}", &video_url);
-        let video_path = {
-            let video_path = self.cache_path.join(
-                Path::new(video_url.path())
-                    .file_name()
-                    .ok_or(anyhow!("No video file name"))?
-                    .to_owned());
-            let mut video_file = File::create(&video_path)?;
-            
-            let response = self.client.get(video_url)
-                .send().await?;
-            video_file.write(&response.bytes().await?)?;
-            video_path
+        // fail before downloading anything if there is no model to transcribe with
+        let model_path = match &self.whisper_server {
+            Some(_) => None,
+            None => Some(self.whisper_model_path()?),
+        };
+        let video_path = if self.transcode_first {
+            tracing::info!("Downloading video to parse captions from: {}", &video_url);
+            self.download_audio(video_url).await?
+        } else if !self.download_video && self.whisper_server.is_none() {
+            // ffmpeg reads the audio over the network without the session cookies, which public
+            // Opencast media urls do not need, and past the download throttle
+            self.check_streamed_video_size(&video_url).await?;
+            tracing::info!("Streaming video to parse captions from: {}", &video_url);
+            PathBuf::from(video_url.as_str())
+        } else {
+            tracing::info!("Downloading video to parse captions from: {}", &video_url);
+            self.download_video(video_url).await?
+        };
+
+        let _permit = self.whisper_permits.acquire().await?;
+        let transcript = match (&self.whisper_server, model_path) {
+            (Some(whisper_server), _) => self.get_server_transcript(whisper_server, &video_path).await?,
+            (None, Some(model_path)) => STTContext::get_whisper_transcript(video_path, model_path, resume_path, self.whisper_decoding.clone(), self.whisper_chunking).await?,
+            (None, None) => unreachable!("model path is resolved whenever there is no whisper server"),
         };
-        
-        let transcript = STTContext::get_whisper_transcript(video_path).await?;
         
         Ok(transcript)
     }
+
+    /// The configured whisper model, falling back to the `WHISPER_MODEL` environment variable
+    fn whisper_model_path(&self) -> anyhow::Result<PathBuf> {
+        if let Some(whisper_model) = &self.whisper_model {
+            return Ok(whisper_model.clone());
+        }
+        std::env::var_os("WHISPER_MODEL")
+            .map(PathBuf::from)
+            .ok_or(anyhow!("No whisper model, set whisper_model in the config or the WHISPER_MODEL environment variable, e.g. to a model fetched with download-model"))
+    }
+
+    /// Transcribe a video by uploading its audio track as mp3 to an OpenAI compatible whisper server
+    pub async fn get_server_transcript(&self, whisper_server: &WhisperServer, video_path: &Path) -> anyhow::Result<Transcript> {
+        let audio = if video_path.extension().is_some_and(|extension| extension == "mp3") {
+            // already transcoded by --transcode-first
+            tokio::fs::read(video_path).await?
+        } else {
+            let audio_path = self.audio_dir()?
+                .join(video_path.file_name().ok_or(anyhow!("No video file name"))?)
+                .with_extension("mp3");
+            Self::transcode_to_mp3(video_path, &audio_path).await?;
+            let audio = tokio::fs::read(&audio_path).await?;
+            tokio::fs::remove_file(&audio_path).await?;
+            audio
+        };
+
+        tracing::info!("Uploading {} bytes of audio to {}", audio.len(), whisper_server.url);
+        let mut form = Form::new()
+            .text("model", whisper_server.model.clone())
+            .text("response_format", "verbose_json")
+            .part("file", Part::bytes(audio).file_name("audio.mp3").mime_str("audio/mpeg")?);
+        // the server detects the language itself if none is given
+        if let Some(language) = self.whisper_decoding.language.as_ref().filter(|language| *language != "auto") {
+            form = form.text("language", language.clone());
+        }
+        // uploading and transcribing a recording takes as long as downloading it
+        let mut request = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.download_timeout)
+            .build()?
+            .post(whisper_server.url.clone())
+            .multipart(form);
+        if let Some(api_key) = &whisper_server.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send().await?
+            .error_for_status()
+            .context("Whisper server rejected transcription request")?
+            .text().await?;
+        let response = json::parse(&response)
+            .context("Failed to parse whisper server response json")?;
+
+        let segments = response["segments"].members()
+            .filter_map(|segment| Some(Segment {
+                start_ms: (segment["start"].as_f64()? * 1000.0) as u64,
+                end_ms: (segment["end"].as_f64()? * 1000.0) as u64,
+                text: segment["text"].as_str()?.trim().to_string(),
+            }))
+            .collect::<Vec<_>>();
+        if !segments.is_empty() {
+            return Ok(Transcript { segments });
+        }
+
+        // plain json responses carry no timing information
+        let text = response["text"].as_str()
+            .ok_or(anyhow!("Whisper server response contains no text"))?;
+        Ok(Transcript {
+            segments: vec![Segment {
+                start_ms: 0,
+                end_ms: 0,
+                text: text.trim().to_string(),
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use reqwest::header::HeaderMap;
+    use crate::client::{LoginData, SessionBuilder, TUWElClientBuilder, Timeouts};
+
+    fn config(regex: &str) -> PatternConfig {
+        PatternConfig {
+            name: regex.to_string(),
+            regex: regex.to_string(),
+            case_insensitive: true,
+            whole_word: true,
+            after_lowercase: false,
+            normalize_whitespace: false,
+            collapse_hyphens: false,
+        }
+    }
+
+    fn pattern(regex: &str, normalize_whitespace: bool, collapse_hyphens: bool) -> Pattern {
+        Pattern::compile(&PatternConfig {
+            normalize_whitespace,
+            collapse_hyphens,
+            ..config(regex)
+        }).unwrap()
+    }
+
+    /// Answer one request per connection on a local port with `responses` in order, counting the
+    /// requests
+    fn serve(responses: Vec<String>) -> (Url, Arc<AtomicUsize>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        std::thread::spawn(move || {
+            for (response, stream) in responses.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                counted.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    /// A response closing its connection, so every request reaches [`serve`] on a new one
+    fn response(status: &str, body: &str) -> String {
+        format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+    }
+
+    fn unavailable() -> String {
+        response("503 Service Unavailable", "")
+    }
+
+    /// A client of an anonymous session caching into `cache_path`, with every option at its default
+    async fn test_client(cache_path: &Path) -> DefactoClient {
+        let client = TUWElClientBuilder {
+            base_url: Url::parse("https://tuwel.example/").unwrap(),
+            login_data: LoginData {
+                username: String::new(),
+                password: String::new(),
+                totp: String::new(),
+                skewed_totps: vec![],
+            },
+            session: SessionBuilder::Anonymous(Some(cache_path.to_path_buf())),
+            default_headers: HeaderMap::new(),
+            timeouts: Timeouts { request: Duration::from_secs(10), connect: Duration::from_secs(10) },
+            session_check_path: "my/".to_string(),
+        }
+            .build().await.unwrap();
+        DefactoClient {
+            client,
+            course_urls: vec![],
+            cache_path: cache_path.to_path_buf(),
+            cache_layout: CacheLayout::default(),
+            clip_export: None,
+            search_api_url: None,
+            caption_languages: vec!["de".to_string()],
+            allow_any_language: false,
+            sequences: vec![],
+            discovery_concurrency: 1,
+            max_retries: 3,
+            download_timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(10),
+            download_throttle: None,
+            video_permits: Arc::new(Semaphore::new(1)),
+            whisper_permits: Arc::new(Semaphore::new(1)),
+            post_processors: vec![],
+            resume_whisper: false,
+            video_config_pattern: None,
+            snapshot: None,
+            whisper_server: None,
+            whisper_model: None,
+            whisper_decoding: WhisperDecoding::default(),
+            whisper_chunking: WhisperChunking::default(),
+            audio_stream_preference: AudioStreamPreference::default(),
+            fallback_policy: FallbackPolicy::default(),
+            skip_episodes: vec![],
+            captions_only: false,
+            captionless_links: None,
+            #[cfg(feature = "ocr")]
+            slide_ocr: false,
+            transcode_first: false,
+            download_video: false,
+            max_video_size: None,
+            vtt_export: None,
+            compare: None,
+            include_segments: false,
+            no_transcript_cache: false,
+            show_progress: false,
+            done_links: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn normalized_offsets_point_into_the_original() {
+        let text = "Ä  de\u{2011}facto\t\n-";
+        let (normalized, offsets) = normalize(text, true, true);
+        assert_eq!(normalized, "Ä defacto -");
+        assert_eq!(offsets.len(), normalized.len() + 1);
+        for (index, char) in normalized.char_indices().filter(|(_, char)| !char.is_whitespace()) {
+            assert!(text[offsets[index]..].starts_with(char));
+        }
+        assert_eq!(offsets.last(), Some(&text.len()));
+    }
+
+    #[test]
+    fn normalized_matches_map_back_to_original_ranges() {
+        let text = "Das ist de-facto so, also\n\t de   facto.";
+        let ranges = pattern("de ?facto", true, true).find_ranges(text);
+        assert_eq!(ranges.iter().map(|range| &text[range.clone()]).collect::<Vec<_>>(),
+                   ["de-facto", "de   facto"]);
+    }
+
+    #[test]
+    fn patterns_share_normalized_copies() {
+        let text = MatchText::new("Ergibt   das Sinn, de-facto?");
+        let sinn = pattern("ergibt das sinn", true, false);
+        let facto = pattern("defacto", false, true);
+        let both = pattern("de ?facto", true, true);
+        assert_eq!(sinn.find_ranges_in(&text), vec![0..17]);
+        assert_eq!(facto.find_ranges_in(&text), vec![19..27]);
+        assert_eq!(both.find_ranges_in(&text), vec![19..27]);
+        assert_eq!(text.normalized.iter().filter(|copy| copy.get().is_some()).count(), 3);
+    }
+
+    fn row(link: &str, course: &str, transcript: &str) -> DataRow {
+        serde_json::from_value(serde_json::json!({
+            "title": link,
+            "link": link,
+            "course": course,
+            "transcript": transcript,
+            "counts": count_patterns(transcript),
+            "word_count": transcript.split_whitespace().count(),
+            "duration_ms": 1000,
+            "sequence_counts": {},
+            "matches": [],
+            "warnings": [],
+        })).unwrap()
+    }
+
+    #[test]
+    fn course_totals_are_per_course() {
+        let totals = DataRow::course_totals(vec![
+            row("a", "course 1", "de facto"),
+            row("b", "course 2", "trivial"),
+            row("c", "course 1", "defacto trivial"),
+        ]);
+        let totals = totals.iter()
+            .map(|total| (total.link(), total.course(), total.counts()["De facto"], total.counts()["trivial"], total.word_count))
+            .collect::<Vec<_>>();
+        assert_eq!(totals, [("course 1", "course 1", 2, 1, 4), ("course 2", "course 2", 0, 1, 1)]);
+    }
+
+    #[test]
+    fn oversized_videos_are_skipped() {
+        assert!(check_video_size(2048, None).is_ok());
+        assert!(check_video_size(1024, Some(1024)).is_ok());
+        let err = anyhow::Error::from(check_video_size(1025, Some(1024)).unwrap_err())
+            .context("Failed to transcribe");
+        assert_eq!(err.downcast_ref(), Some(&TranscriptError::SkipTooLarge { size: 1025, max_size: 1024 }));
+    }
+
+    #[test]
+    fn episode_object_ends_at_its_closing_brace() {
+        let script = r#"
+            window.episode = {"id": "a}b", "streams": [{"content": "presenter"}]};
+            window.player = {"autoplay": false};
+        "#;
+        assert_eq!(DefactoClient::extract_episode_json(script).unwrap(),
+                   r#"{"id": "a}b", "streams": [{"content": "presenter"}]}"#);
+    }
+
+    #[test]
+    fn episode_object_is_found_in_every_script_wrapper() {
+        let scripts = [
+            "window.episode={\"id\": \"1\"};",
+            "//<![CDATA[\n    window.episode  =  {\"id\": \"1\"};\n//]]>",
+            "\r\n//<![CDATA[\r\n\twindow.episode =\r\n{\"id\": \"1\"}\r\n//]]>\r\n",
+            "window.episode = {\"id\": \"1\"}\n//]]>",
+        ];
+        for script in scripts {
+            assert_eq!(DefactoClient::extract_episode_json(script).unwrap(), r#"{"id": "1"}"#, "{script:?}");
+        }
+    }
+
+    #[test]
+    fn missing_or_broken_episode_object_fails() {
+        assert!(DefactoClient::extract_episode_json("window.player = {};").is_err());
+        assert!(DefactoClient::extract_episode_json("window.episode = {\"id\": ").is_err());
+    }
+
+    #[test]
+    fn episode_errors_quote_the_start_of_the_script() {
+        let padding = "x".repeat(300);
+        for script in [format!("window.player = {{}}; // {padding}"), format!("window.episode = {{\"id\": }}; // {padding}")] {
+            let err = format!("{:#}", DefactoClient::extract_episode_json(&script).unwrap_err());
+            let snippet = format!("{}…", script.chars().take(SCRIPT_SNIPPET_CHARS).collect::<String>());
+            assert!(err.contains(&snippet), "{err}");
+        }
+    }
+
+    #[test]
+    fn count_columns_follow_pattern_declaration_order() {
+        assert_eq!(pattern_names().collect::<Vec<_>>(), ["De facto", "trivial", "Ergibt das Sinn"]);
+        let shortened: ShortenedDataRow = row("a", "", "ergibt das Sinn, trivial, trivial").into();
+        let header = ShortenedDataRow::csv_header(pattern_names());
+        let record = shortened.csv_record();
+        let count = |name: &str| &record[header.iter().position(|column| column == name).unwrap()];
+        assert_eq!((count("De facto"), count("trivial"), count("Ergibt das Sinn")), ("0", "2", "1"));
+        assert_eq!(header.len(), record.len());
+    }
+
+    #[test]
+    fn count_columns_are_byte_identical_across_runs() {
+        let toml = [
+            "Zeta", "Alpha", "Mitte", "Beta", "Omega", "Delta",
+        ].map(|name| format!("[[patterns]]\nname = \"{name}\"\nregex = \"{}\"\n", name.to_lowercase())).concat();
+        let header = || {
+            let config: config::Config = toml::from_str(&toml).unwrap();
+            let patterns = config.patterns.iter()
+                .map(|config| Pattern::compile(config).unwrap())
+                .collect::<Vec<_>>();
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(&DataRow::csv_header(patterns.iter().map(|pattern| pattern.name.as_str()), [])).unwrap();
+            writer.into_inner().unwrap()
+        };
+        let first = header();
+        assert_eq!(first, header());
+        let first = String::from_utf8(first).unwrap();
+        assert!(first.starts_with("title,link,course,transcript,word_count,words_per_minute,Zeta,Alpha,Mitte,Beta,Omega,Delta,"), "{first}");
+    }
+
+    #[test]
+    fn recording_links_are_read_from_hrefs_and_data_attributes() {
+        let page = XHtml::new(r#"<html><body><div id="region-main"><table><tbody>
+            <tr><td><a href="https://tuwel.example/opencast/play.php?e=1">Lecture 1</a></td></tr>
+            <tr data-href="play.php?e=2"><td>Lecture 2</td></tr>
+            <tr><td><span data-episode-id="3">Lecture 3</span></td></tr>
+            <tr><td>Announcement</td></tr>
+        </tbody></table></div></body></html>"#).unwrap();
+        let course = Url::parse("https://tuwel.example/opencast/play.php?id=7").unwrap();
+        assert_eq!(DefactoClient::get_page_video_links(&page, &course).unwrap(), [
+            "https://tuwel.example/opencast/play.php?e=1",
+            "https://tuwel.example/opencast/play.php?e=2",
+            "https://tuwel.example/opencast/play.php?id=7&e=3",
+        ]);
+    }
+
+    #[test]
+    fn recording_table_is_found_by_its_links_wherever_it_is() {
+        let page = XHtml::new(r#"<html><body>
+            <div id="region-main"><table><tbody><tr><td>Layout</td></tr></tbody></table></div>
+            <aside><div><table><tbody>
+                <tr><td><a href="https://tuwel.example/opencast/play.php?e=1">Lecture 1</a></td></tr>
+            </tbody></table></div></aside>
+        </body></html>"#).unwrap();
+        let course = Url::parse("https://tuwel.example/opencast/play.php?id=7").unwrap();
+        assert_eq!(DefactoClient::get_page_video_links(&page, &course).unwrap(), [
+            "https://tuwel.example/opencast/play.php?e=1",
+        ]);
+    }
+
+    #[test]
+    fn missing_recording_table_is_an_error() {
+        let page = XHtml::new("<html><body><p>Keine Aufzeichnungen</p></body></html>").unwrap();
+        let course = Url::parse("https://tuwel.example/opencast/play.php?id=7").unwrap();
+        let err = DefactoClient::get_page_video_links(&page, &course).unwrap_err();
+        assert!(err.to_string().contains("Could not find the recording table"));
+    }
+
+    #[test]
+    fn pagination_links_of_the_same_list_are_followed() {
+        let page = XHtml::new(r#"<html><body>
+            <ul class="pagination">
+                <li><a href="?id=7&amp;page=0">1</a></li>
+                <li><a href="?id=7&amp;page=1">2</a></li>
+            </ul>
+            <a href="/course/view.php?id=7">Course</a>
+        </body></html>"#).unwrap();
+        let page_url = Url::parse("https://tuwel.example/opencast/list.php?id=7").unwrap();
+        assert_eq!(DefactoClient::get_page_urls(&page, &page_url).unwrap().iter().map(Url::as_str).collect::<Vec<_>>(), [
+            "https://tuwel.example/opencast/list.php?id=7&page=0",
+            "https://tuwel.example/opencast/list.php?id=7&page=1",
+        ]);
+    }
+
+    #[test]
+    fn rel_next_link_is_followed() {
+        let page = XHtml::new(r#"<html><body>
+            <nav><a rel="next" href="list.php?id=7&amp;page=2">Next</a></nav>
+        </body></html>"#).unwrap();
+        let page_url = Url::parse("https://tuwel.example/opencast/list.php?id=7&page=1").unwrap();
+        assert_eq!(DefactoClient::get_page_urls(&page, &page_url).unwrap().iter().map(Url::as_str).collect::<Vec<_>>(), [
+            "https://tuwel.example/opencast/list.php?id=7&page=2",
+        ]);
+    }
+
+    #[test]
+    fn whole_word_option_decides_about_matches_inside_words() {
+        let text = "nontrivial, trivial";
+        assert_eq!(Pattern::compile(&config("trivial")).unwrap().find_ranges(text), vec![12..19]);
+        let anywhere = Pattern::compile(&PatternConfig { whole_word: false, ..config("trivial") }).unwrap();
+        assert_eq!(anywhere.find_ranges(text), vec![3..10, 12..19]);
+    }
+
+    #[test]
+    fn after_lowercase_skips_matches_starting_a_sentence() {
+        let pattern = Pattern::compile(&PatternConfig { after_lowercase: true, ..config("de facto") }).unwrap();
+        let text = "De facto gilt. Das ist de facto so. Dann: De facto";
+        assert_eq!(count_patterns_with(text, &[pattern])["de facto"], 1);
+    }
+
+    #[test]
+    fn matches_at_the_ends_and_back_to_back_all_count() {
+        let counts = count_patterns("trivial");
+        assert_eq!(counts["trivial"], 1);
+        let counts = count_patterns("Trivial trivial\ntrivial");
+        assert_eq!(counts["trivial"], 3);
+        assert_eq!(match_ranges("de facto de facto").iter().map(|(_, range)| range.clone()).collect::<Vec<_>>(), [0..8, 9..17]);
+    }
+
+    #[test]
+    fn cached_transcript_is_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0a1b.transcript.json");
+        let cached = CachedTranscript {
+            title: "Lecture 1".to_string(),
+            link: "https://tuwel.example/opencast/play.php?e=1".to_string(),
+            source: TranscriptSource::Captions,
+            transcript: Transcript {
+                segments: vec![Segment { start_ms: 0, end_ms: 1_500, text: "de facto".to_string() }],
+            },
+        };
+        DefactoClient::cache_transcript(&path, &cached).unwrap();
+        let read = DefactoClient::read_cached_transcript(&path).unwrap();
+        assert_eq!((read.title, read.link, read.source, read.transcript),
+                   (cached.title, cached.link, cached.source, cached.transcript));
+    }
+
+    #[test]
+    fn transcript_cached_without_source_is_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0a1b.transcript.json");
+        std::fs::write(&path, r#"{"title": "Lecture 1", "link": "l", "transcript": {"segments": []}}"#).unwrap();
+        assert_eq!(DefactoClient::read_cached_transcript(&path).unwrap().source, TranscriptSource::Unknown);
+    }
+
+    #[test]
+    fn rates_are_per_thousand_words() {
+        assert_eq!(rate_per_thousand_words(3, 1500), Some(2.0));
+        assert_eq!(rate_per_thousand_words(1, 0), None);
+
+        let counts = IndexMap::from([("trivial".to_string(), 1), ("De facto".to_string(), 0)]);
+        assert_eq!(rate_fields(&counts, 800).collect::<Vec<_>>(), ["1.25", "0.00"]);
+        assert_eq!(rate_fields(&counts, 0).collect::<Vec<_>>(), ["", ""]);
+    }
+
+    #[test]
+    fn srt_captions_are_parsed_without_webvtt_header() {
+        let srt = "1\n00:00:01,000 --> 00:00:03,500\nDas ist\ntrivial.\n\n2\n00:00:04,000 --> 00:00:05,000\nDe facto.\n";
+        let transcript = DefactoClient::parse_subtitles(srt).unwrap();
+        assert_eq!(transcript.segments.iter().map(|segment| (segment.start_ms, segment.end_ms, segment.text.as_str())).collect::<Vec<_>>(), [
+            (1_000, 3_500, "Das ist trivial."),
+            (4_000, 5_000, "De facto."),
+        ]);
+    }
+
+    #[test]
+    fn vtt_captions_are_parsed_by_their_header() {
+        let vtt = "\u{feff}WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nDas ist trivial.\n";
+        let transcript = DefactoClient::parse_subtitles(vtt).unwrap();
+        assert_eq!(transcript.text(), "Das ist trivial.");
+        assert!(DefactoClient::parse_subtitles("WEBVTT\n").is_err());
+    }
+
+    #[test]
+    fn words_are_split_at_any_whitespace() {
+        let text = " das ist\n trivial";
+        assert_eq!(word_ranges(text).into_iter().map(|range| &text[range]).collect::<Vec<_>>(), ["das", "ist", "trivial"]);
+        assert!(word_ranges("  ").is_empty());
+    }
+
+    #[test]
+    fn contexts_quote_the_surrounding_words() {
+        let contexts = match_contexts("also das ist \n trivial und so weiter", 2);
+        assert_eq!(contexts.len(), 1);
+        assert_eq!((contexts[0].pattern.as_str(), contexts[0].snippet.as_str(), contexts[0].offset), ("trivial", "das ist trivial und so", 15));
+
+        let contexts = match_contexts("trivial", 3);
+        assert_eq!(contexts[0].snippet, "trivial");
+    }
+
+    #[test]
+    fn sources_without_resolution_are_skipped() {
+        let stream = |sources: serde_json::Value| serde_json::from_value::<Stream>(serde_json::json!({ "sources": { "mp4": sources } })).unwrap();
+        let sized = stream(serde_json::json!([
+            { "src": "unknown.mp4" },
+            { "src": "720p.mp4", "res": { "w": 1280, "h": 720 } },
+            { "src": "360p.mp4", "res": { "w": 640, "h": 360 } },
+        ]));
+        assert_eq!(DefactoClient::get_sized_source(&sized, true), Some("720p.mp4"));
+        assert_eq!(DefactoClient::get_stream_source(&sized), Some("360p.mp4"));
+
+        let unknown = stream(serde_json::json!([{ "src": "unknown.mp4" }]));
+        assert_eq!(DefactoClient::get_stream_source(&unknown), Some("unknown.mp4"));
+    }
+
+    /// A mono 16 bit PCM WAV file of a 440Hz tone
+    fn write_wav(path: &Path, rate: u32, samples: u32) {
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + samples * 2).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(rate.to_le_bytes());
+        wav.extend((rate * 2).to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend((samples * 2).to_le_bytes());
+        for index in 0..samples {
+            let sample = (index as f32 * 440.0 * std::f32::consts::TAU / rate as f32).sin() * 8_000.0;
+            wav.extend((sample as i16).to_le_bytes());
+        }
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn audio_is_decoded_to_other_rates() {
+        ffmpeg_next::init().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        write_wav(&path, 8_000, 8_000);
+
+        let format = AudioFormat { sample: Sample::F32(sample::Type::Packed), rate: 4_000, ..AudioFormat::default() };
+        let audio = decode_audio(&path, format).unwrap();
+        // the resampler holds back a few samples at the end
+        assert!((3_900..=4_000).contains(&audio.len()), "{} samples", audio.len());
+        assert!(audio.iter().any(|sample| sample.abs() > 0.1));
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_until_success() {
+        let (url, requests) = serve(vec![unavailable(), unavailable(), response("200 OK", "ok")]);
+        let cache = tempfile::tempdir().unwrap();
+        let client = test_client(cache.path()).await;
+
+        let response = client.get(url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn last_server_error_is_returned_once_retries_are_used_up() {
+        let (url, requests) = serve(vec![unavailable(), unavailable()]);
+        let cache = tempfile::tempdir().unwrap();
+        let client = DefactoClient { max_retries: 1, ..test_client(cache.path()).await };
+
+        let response = client.get(url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn permits_cap_the_videos_and_transcriptions_in_flight() {
+        let config: config::Config = toml::from_str("concurrency = 3\nwhisper_concurrency = 2").unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let client = DefactoClient {
+            video_permits: permits(config.concurrency),
+            whisper_permits: permits(config.whisper_concurrency),
+            ..test_client(cache.path()).await
+        };
+
+        #[derive(Default)]
+        struct InFlight {
+            current: AtomicUsize,
+            max: AtomicUsize,
+        }
+        impl InFlight {
+            async fn hold(&self, duration: Duration) {
+                let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(duration).await;
+                self.current.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        let videos = Arc::new(InFlight::default());
+        let transcriptions = Arc::new(InFlight::default());
+
+        // acquired the way do_stuff and get_whisper_transcript do
+        let mut tasks = JoinSet::new();
+        for _ in 0..12 {
+            let client = client.clone();
+            let videos = videos.clone();
+            let transcriptions = transcriptions.clone();
+            tasks.spawn(async move {
+                let _video_permit = client.video_permits.clone().acquire_owned().await.unwrap();
+                videos.hold(Duration::from_millis(5)).await;
+                let _whisper_permit = client.whisper_permits.acquire().await.unwrap();
+                tokio::join!(videos.hold(Duration::from_millis(10)), transcriptions.hold(Duration::from_millis(10)));
+            });
+        }
+        while let Some(joined) = tasks.join_next().await {
+            joined.unwrap();
+        }
+
+        assert_eq!(videos.max.load(Ordering::SeqCst), 3);
+        assert_eq!(transcriptions.max.load(Ordering::SeqCst), 2);
+        assert_eq!(permits(0).available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn resumed_run_processes_only_the_missing_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.csv");
+        let header = DataRow::csv_header(pattern_names(), []);
+        let link = |episode: u32| format!("https://tuwel.example/opencast/play.php?e={episode}");
+
+        // the first run was interrupted after two videos
+        let mut writer = output::append_csv(&path, &header).unwrap();
+        for episode in [2, 1] {
+            writer.write_record(&row(&link(episode), "", "trivial").csv_record(true, None)).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let done_links = output::existing_links(&path, &header).unwrap().unwrap();
+        let client = DefactoClient { done_links, ..test_client(dir.path()).await };
+        let links = [3, 1, 4, 2].map(|episode| (link(episode), String::new())).to_vec();
+        assert_eq!(client.pending_links(links), [(link(3), String::new()), (link(4), String::new())]);
+
+        let mut writer = output::append_csv(&path, &header).unwrap();
+        writer.write_record(&row(&link(3), "", "de facto").csv_record(true, None)).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        assert_eq!(reader.headers().unwrap(), &header);
+        let links = reader.records()
+            .map(|record| record.unwrap()[1].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(links, [link(2), link(1), link(3)]);
+    }
+
+    #[tokio::test]
+    async fn captions_download_while_whisper_runs() {
+        let (url, _) = serve(vec![response("200 OK", "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nDas ist trivial.\n")]);
+        let cache = tempfile::tempdir().unwrap();
+        let client = test_client(cache.path()).await;
+
+        // the test runtime has a single thread, which inference run on it would block
+        let whisper = run_blocking(|| {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(std::time::Instant::now())
+        });
+        let captions = async {
+            let transcript = client.get_opencast_transcript(url).await.unwrap();
+            (transcript, std::time::Instant::now())
+        };
+        let (whisper_done, (transcript, captions_done)) = tokio::join!(whisper, captions);
+        assert_eq!(transcript.text(), "Das ist trivial.");
+        assert!(captions_done < whisper_done.unwrap());
+    }
+
+    #[tokio::test]
+    async fn language_fallback_is_warned_about_once_per_video() {
+        let (url, _) = serve(vec![response("200 OK", "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nThat is trivial.\n")]);
+        let video_config = EpisodeConfig::parse(&serde_json::json!({
+            "metadata": { "title": "Lecture 1" },
+            "captions": [{ "lang": "en", "format": "vtt", "url": url }],
+        }).to_string()).unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let client = DefactoClient { allow_any_language: true, ..test_client(cache.path()).await };
+
+        let (transcript, warnings) = warnings::collect(async {
+            assert!(client.get_caption_url(&video_config).is_some());
+            client.get_current_caption_transcript("https://tuwel.example/opencast/play.php?e=1", &video_config).await
+        }).await;
+        assert_eq!(transcript.unwrap().text(), "That is trivial.");
+        assert_eq!(warnings, [r#"No captions in ["de"] found, fell back to language en"#]);
+    }
+
+    #[tokio::test]
+    async fn cached_transcript_is_used_without_requests() {
+        let (url, requests) = serve(vec![response("200 OK", "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nDas ist trivial.\n")]);
+        let video_config = EpisodeConfig::parse(&serde_json::json!({
+            "metadata": { "title": "Vorlesung 1" },
+            "captions": [{ "lang": "de", "format": "vtt", "url": url }],
+        }).to_string()).unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let client = test_client(cache.path()).await;
+        let link = "https://tuwel.example/opencast/play.php?e=1";
+
+        let fetched = client.get_transcript(link, &video_config).await.unwrap();
+        let cached = client.get_transcript(link, &video_config).await.unwrap();
+        assert_eq!(cached.text(), fetched.text());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn transcript_cache_is_keyed_by_the_chosen_captions() {
+        let video_config = EpisodeConfig::parse(r#"{
+            "metadata": { "title": "Vorlesung 1" },
+            "captions": [
+                { "lang": "en", "format": "vtt", "url": "https://opencast.example/en.vtt" },
+                { "lang": "de", "format": "vtt", "url": "https://opencast.example/de.vtt" }
+            ]
+        }"#).unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let german = test_client(cache.path()).await;
+        let english = DefactoClient { caption_languages: vec!["en".to_string()], ..german.clone() };
+        assert_ne!(german.transcript_cache_path(&video_config).unwrap(), english.transcript_cache_path(&video_config).unwrap());
+    }
+
+    #[test]
+    fn default_de_facto_pattern_matches_through_its_options() {
+        let de_facto = config::default_patterns().remove(0);
+        let texts = ["defacto", "de  facto", "de\u{2011}facto", "De\nfacto"];
+        let pattern = Pattern::compile(&de_facto).unwrap();
+        for text in texts {
+            assert_eq!(pattern.find_ranges(text), vec![0..text.len()], "{text:?}");
+        }
+
+        let regex_only = Pattern::compile(&PatternConfig { normalize_whitespace: false, collapse_hyphens: false, ..de_facto }).unwrap();
+        assert_eq!(texts.map(|text| regex_only.find_ranges(text).len()), [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn default_patterns_count_every_form() {
+        let counts = count_patterns("De facto defacto de-facto, trivial.Trivial ergibt das\nSinn");
+        assert_eq!(counts.values().copied().collect::<Vec<_>>(), [3, 2, 1]);
+        assert_eq!(count_patterns("nontrivial Defactoring")["De facto"], 0);
+    }
 }
@@ -0,0 +1,120 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// The Paella player episode manifest embedded in a video page
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EpisodeConfig {
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub streams: Vec<Stream>,
+    #[serde(default)]
+    pub captions: Vec<Caption>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+impl EpisodeConfig {
+    /// Deserialize an episode manifest, naming the path of the offending field on mismatches
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let deserializer = &mut serde_json::Deserializer::from_str(json);
+        serde_path_to_error::deserialize(deserializer)
+            .context("Failed to deserialize episode config")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Metadata {
+    pub title: String,
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Recording length in seconds
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+/// A single recorded video track, e.g. the presenter camera or the slides
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Stream {
+    /// What the stream shows, e.g. `presenter` or `presentation`
+    #[serde(default)]
+    pub content: Option<String>,
+    /// `mainAudio` for the stream whose audio track is played
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub sources: Sources,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Sources {
+    #[serde(default)]
+    pub mp4: Vec<Mp4Source>,
+    /// Audio only renditions, exposed by some Opencast installations next to the videos
+    #[serde(default)]
+    pub audio: Vec<AudioSource>,
+}
+
+/// One resolution of a stream available as mp4
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mp4Source {
+    pub src: String,
+    /// Missing on some Opencast installations
+    #[serde(default)]
+    pub res: Option<Resolution>,
+}
+
+impl Mp4Source {
+    pub fn pixels(&self) -> Option<usize> {
+        self.res.map(|res| res.w * res.h)
+    }
+}
+
+/// An audio only rendition of a stream
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AudioSource {
+    pub src: String,
+    #[serde(default)]
+    pub mimetype: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Resolution {
+    pub w: usize,
+    pub h: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Caption {
+    pub lang: String,
+    /// File format of the captions, e.g. `vtt`
+    pub format: String,
+    pub url: String,
+}
+
+/// A document attached to the episode, e.g. a transcript of the lecture
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Attachment {
+    /// Opencast flavor of the attachment, e.g. `transcript/delivery`
+    #[serde(default, rename = "type")]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub mimetype: Option<String>,
+    pub url: String,
+}
+
+impl Attachment {
+    /// Whether this is a plain text or PDF transcript of the lecture
+    pub fn is_transcript(&self) -> bool {
+        let is_transcript = self.kind.as_deref()
+            .is_some_and(|kind| kind.to_lowercase().contains("transcript"));
+        is_transcript && (self.is_pdf() || self.is_text())
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.mimetype.as_deref().is_some_and(|mimetype| mimetype.starts_with("text/plain")) || self.url.ends_with(".txt")
+    }
+
+    pub fn is_pdf(&self) -> bool {
+        self.mimetype.as_deref() == Some("application/pdf") || self.url.ends_with(".pdf")
+    }
+}
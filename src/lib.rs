@@ -0,0 +1,24 @@
+//! Count phrases like "de facto" in the lecture recordings of TUWEL courses
+//!
+//! A [`DefactoClient`](defacto::DefactoClient) processes the Opencast recordings of courses into
+//! [`DataRow`](defacto::DataRow)s, e.g. as a stream with
+//! [`course_rows`](defacto::DefactoClient::course_rows). Patterns can also be counted in any text
+//! with [`count_patterns_with`](defacto::count_patterns_with).
+
+pub mod bandwidth;
+pub mod client;
+pub mod clip;
+pub mod config;
+pub mod database;
+pub mod defacto;
+pub mod episode;
+pub mod model;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+pub mod output;
+pub mod postprocess;
+pub mod retry;
+pub mod seam;
+pub mod snapshot;
+pub mod transcript;
+pub mod warnings;
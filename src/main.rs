@@ -1,34 +1,268 @@
-mod client;
-mod config;
-mod defacto;
-
-use crate::client::{LoginData, SessionBuilder, TUWElClientBuilder};
-use crate::config::Config;
-use crate::defacto::{DefactoClient, ShortenedDataRow};
-use anyhow::Context;
+mod cli;
+
+use crate::cli::{Cli, Command};
+use ::defacto::bandwidth;
+use ::defacto::client::{self, LoginData, SessionBuilder, TUWElClientBuilder, Timeouts};
+use ::defacto::clip::ClipExport;
+use ::defacto::config::{self, Config, WhisperDecoding};
+use ::defacto::database::Database;
+use ::defacto::defacto::{self, DataRow, DefactoClient, RunReport, ShortenedDataRow};
+use ::defacto::model;
+use ::defacto::output::{self, JsonArrayWriter, OutputFormat, RotatingCsvWriter, RotationLimit};
+use ::defacto::postprocess::PostProcessor;
+use ::defacto::snapshot::Snapshot;
+use ::defacto::transcript::TranscriptSource;
+use anyhow::{anyhow, Context};
+use clap::Parser;
+use csv::StringRecord;
+use indexmap::IndexMap;
+use regex::Regex;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing_subscriber::EnvFilter;
 
+/// Print every pattern match in `text` with surrounding context, followed by per-pattern totals
+fn test_patterns(text: &str, context_words: usize) {
+    let contexts = defacto::match_contexts(text, context_words);
+    for context in &contexts {
+        println!("{} @ {}: {}", context.pattern, context.offset, context.snippet);
+    }
+
+    println!();
+    for name in defacto::pattern_names() {
+        let count = contexts.iter().filter(|context| context.pattern == name).count();
+        println!("{name}: {count}");
+    }
+}
+
+/// Format a duration as hours and minutes, e.g. `3h 05m`
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+/// Read one playback link per line, ignoring empty lines
+fn read_links(path: &Path) -> anyhow::Result<Vec<String>> {
+    let links = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(links.lines()
+        .map(str::trim)
+        .filter(|link| !link.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Totals of the rows of a run, printed when it is done
+struct Summary {
+    sources: IndexMap<TranscriptSource, usize>,
+    matches: IndexMap<&'static str, usize>,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Summary {
+            sources: IndexMap::new(),
+            matches: defacto::pattern_names().map(|name| (name, 0)).collect(),
+        }
+    }
+
+    fn add(&mut self, row: &DataRow) {
+        *self.sources.entry(row.source()).or_default() += 1;
+        for (name, total) in &mut self.matches {
+            *total += row.counts().get(*name).copied().unwrap_or_default();
+        }
+    }
+
+    /// Print the summary as an aligned table for terminals or as `label: value` lines for scripts
+    fn print(&self, out: &mut impl Write, report: &RunReport, table: bool) -> io::Result<()> {
+        let mut lines = vec![("videos".to_string(), report.videos)];
+        lines.extend(self.sources.iter().map(|(source, count)| (format!("via {}", source.name()), *count)));
+        if !report.captionless.is_empty() {
+            lines.push(("skipped without captions".to_string(), report.captionless.len()));
+        }
+        if !report.skipped.is_empty() {
+            lines.push(("skipped as too large".to_string(), report.skipped.len()));
+        }
+        lines.push(("failed".to_string(), report.failed));
+        lines.extend(self.matches.iter().map(|(name, total)| (format!("matches {name}"), *total)));
+
+        if !table {
+            for (label, value) in lines {
+                writeln!(out, "{label}: {value}")?;
+            }
+            return Ok(());
+        }
+        let label_width = lines.iter().map(|(label, _)| label.chars().count()).max().unwrap_or_default();
+        let value_width = lines.iter().map(|(_, value)| value.to_string().len()).max().unwrap_or_default();
+        writeln!(out, "Summary")?;
+        writeln!(out, "{}", "─".repeat(label_width + value_width + 2))?;
+        for (label, value) in lines {
+            writeln!(out, "{label:<label_width$}  {value:>value_width$}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Create a CSV file of per-video records, or continue it when resuming an earlier run
+fn create_csv(path: &Path, header: &StringRecord, resume: bool) -> anyhow::Result<csv::Writer<File>> {
+    if resume {
+        return output::append_csv(path, header);
+    }
+    let mut writer = csv::Writer::from_writer(File::create(path)?);
+    writer.write_record(header)?;
+    Ok(writer)
+}
+
+async fn persist_session(client: &DefactoClient, session_path: Option<&Path>) -> anyhow::Result<()> {
+    match session_path {
+        Some(session_path) => client.client.persist(session_path).await,
+        None => Ok(()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_env_filter(EnvFilter::from_default_env())
+        // stdout is reserved for results, e.g. with --stdout
+        .with_writer(std::io::stderr)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber)
         .context("Failed to set default tracing subscriber")?;
 
-    let Config { login, cache_path } = Config::load("app.toml")?;
+    if let Some(Command::StorePassword) = cli.command {
+        let username = Config::read(&cli.config)
+            .with_context(|| format!("Failed to load config {}", cli.config.display()))?
+            .login.username;
+        if username.is_empty() {
+            return Err(anyhow!("No username, set it in [login] or DEFACTO_USERNAME"));
+        }
+        let password = rpassword::prompt_password(format!("TUWEL password of {username}: "))?;
+        config::keyring_entry(&username)
+            .and_then(|entry| entry.set_password(&password))
+            .context("Failed to store the password in the OS keyring")?;
+        eprintln!("Stored the password of {username} in the OS keyring");
+        return Ok(());
+    }
+
+    let Config {
+        base_url,
+        mut login,
+        mut course_urls,
+        cache_path,
+        cache_layout,
+        search_api_url,
+        caption_languages,
+        allow_any_language,
+        replacements,
+        skip_episodes,
+        patterns,
+        sequences,
+        session_check_path,
+        discovery_concurrency,
+        concurrency,
+        whisper_concurrency,
+        default_headers,
+        max_retries,
+        request_timeout_seconds,
+        connect_timeout_seconds,
+        download_timeout_seconds,
+        whisper_model,
+        whisper_server,
+        download_video,
+        max_download_bytes_per_sec,
+        audio_stream_preference,
+        fallback_policy,
+        video_config_pattern,
+        whisper_decoding,
+        whisper_chunking,
+        whisper_realtime_factor,
+        sanitize_csv,
+        timestamp_format,
+        match_context_words,
+    } = Config::read(&cli.config)
+        .with_context(|| format!("Failed to load config {}", cli.config.display()))?;
+    defacto::set_patterns(&patterns)?;
+    for course_url in &cli.course_urls {
+        if !course_urls.contains(course_url) {
+            course_urls.push(course_url.clone());
+        }
+    }
+    for sequence in &sequences {
+        if let Some(unknown) = sequence.patterns.iter().find(|pattern| defacto::pattern_names().all(|name| name != *pattern)) {
+            return Err(anyhow!("Sequence {} refers to unknown pattern {unknown}", sequence.name));
+        }
+    }
+    if max_download_bytes_per_sec.is_some() && !download_video && whisper_server.is_none() && !cli.transcode_first {
+        tracing::warn!("max_download_bytes_per_sec does not limit recordings ffmpeg streams for whisper, set download_video to limit them too");
+    }
     std::fs::create_dir_all(&cache_path)?;
+    if let Some(export_dir) = &cli.export_clips {
+        std::fs::create_dir_all(export_dir)?;
+    }
+    if let Some(export_dir) = &cli.export_vtt {
+        std::fs::create_dir_all(export_dir)?;
+    }
+    if let Some(compare_dir) = &cli.compare {
+        std::fs::create_dir_all(compare_dir)?;
+    }
+
+    if let Some(Command::TestPatterns { file, context }) = &cli.command {
+        let text = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        test_patterns(&text, context.unwrap_or(match_context_words));
+        return Ok(());
+    }
+
+    if let Some(Command::DownloadModel { size }) = cli.command {
+        let models_dir = cache_path.join(&cache_layout.models);
+        std::fs::create_dir_all(&models_dir)?;
+        let model_path = model::download(size, &models_dir).await?;
+        println!("Downloaded whisper model to {}", model_path.display());
+        println!("Use it by setting whisper_model in the config or WHISPER_MODEL={}", model_path.display());
+        return Ok(());
+    }
 
-    print!("Please enter your TOTP token: ");
-    std::io::stdout().flush()?;
-    let mut totp = String::new();
-    std::io::stdin().read_line(&mut totp)?;
+    let snapshot = cli.from_snapshot
+        .map(Snapshot::load)
+        .transpose()?;
 
-    let session_path = cache_path.join(".session.json");
-    let session = if session_path.exists() {
+    // recounting cached transcripts and replaying snapshots need no login
+    let offline = snapshot.is_some() || matches!(cli.command, Some(Command::Recount));
+
+    // only commands that log in need the credentials
+    if !offline {
+        login.require()?;
+    }
+    let mut totp = cli.totp.unwrap_or_default();
+    let mut skewed_totps = vec![];
+    if !offline && totp.is_empty() {
+        if let Some(totp_secret) = &login.totp_secret {
+            let mut codes = client::totp_codes(totp_secret)?.into_iter();
+            totp = codes.next().unwrap_or_default();
+            skewed_totps = codes.collect();
+        }
+    }
+    if !offline && totp.is_empty() {
+        // prompting without a terminal blocks forever or reads unrelated input
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow!("stdin is not a terminal, pass the TOTP token with --totp or configure login.totp_secret"));
+        }
+        eprint!("Please enter your TOTP token: ");
+        std::io::stderr().flush()?;
+        std::io::stdin().read_line(&mut totp)?;
+    }
+
+    let session_path = DefactoClient::session_path(&cache_path, &cache_layout)?;
+    let session = if offline {
+        SessionBuilder::Anonymous(Some(cache_path.clone()))
+    } else if session_path.exists() {
         let session_file = File::open(&session_path)?;
         SessionBuilder::Restore(session_file, Some(cache_path.clone()))
     } else {
@@ -36,34 +270,274 @@ async fn main() -> anyhow::Result<()> {
     };
 
     let client = TUWElClientBuilder {
+        base_url,
         login_data: LoginData {
             username: login.username,
             password: login.password,
-            totp: totp.to_string(),
+            totp: totp.trim().to_string(),
+            skewed_totps,
         },
         session,
+        default_headers: client::header_map(&default_headers)?,
+        timeouts: Timeouts {
+            request: Duration::from_secs(request_timeout_seconds),
+            connect: Duration::from_secs(connect_timeout_seconds),
+        },
+        session_check_path,
     }
         .build().await?;
     
+    // a resumed run skips the videos already in the results and appends the new ones
+    let header = DataRow::csv_header(defacto::pattern_names(), sequences.iter().map(|sequence| sequence.name.as_str()));
+    if cli.stdout && cli.format == OutputFormat::Both {
+        return Err(anyhow!("--stdout writes a single format, choose --format csv or json"));
+    }
+    if cli.resume && !cli.format.csv() {
+        return Err(anyhow!("--resume continues {}, which --format json does not write", cli.output.display()));
+    }
+    let done_links = if cli.resume {
+        let done_links = output::existing_links(&cli.output, &header)?;
+        match &done_links {
+            Some(done_links) => eprintln!("Resuming after {} videos in {}", done_links.len(), cli.output.display()),
+            None => eprintln!("{} has other columns than this run writes, starting it over", cli.output.display()),
+        }
+        done_links
+    } else {
+        None
+    };
+    let resuming = done_links.is_some();
+
+    // whisper transcribes in the language captions are preferred in unless told otherwise
+    let whisper_decoding = WhisperDecoding {
+        language: whisper_decoding.language.or_else(|| caption_languages.first().cloned()),
+        ..whisper_decoding
+    };
     let client = DefactoClient {
         client,
+        course_urls: course_urls.clone(),
         cache_path: cache_path.clone(),
-    };
+        cache_layout,
+        clip_export: cli.export_clips.map(|dir| ClipExport {
+            dir,
+            padding: Duration::from_secs(cli.clip_padding),
+        }),
+        search_api_url,
+        caption_languages,
+        allow_any_language,
+        sequences,
+        discovery_concurrency,
+        max_retries,
+        download_timeout: Duration::from_secs(download_timeout_seconds),
+        connect_timeout: Duration::from_secs(connect_timeout_seconds),
+        download_throttle: max_download_bytes_per_sec.map(|bytes_per_sec| Arc::new(bandwidth::Throttle::new(bytes_per_sec))),
+        video_permits: defacto::permits(concurrency),
+        whisper_permits: defacto::permits(whisper_concurrency),
+        post_processors: vec![],
+        resume_whisper: cli.resume_whisper,
+        video_config_pattern: video_config_pattern
+            .map(|pattern| Regex::new(&pattern))
+            .transpose()
+            .context("Invalid video_config_pattern")?,
+        snapshot,
+        whisper_server,
+        whisper_model,
+        whisper_decoding,
+        whisper_chunking,
+        audio_stream_preference,
+        fallback_policy,
+        skip_episodes,
+        captions_only: cli.captions_only,
+        captionless_links: cli.only_captionless
+            .map(|path| read_links(&path))
+            .transpose()?,
+        #[cfg(feature = "ocr")]
+        slide_ocr: cli.slide_ocr,
+        transcode_first: cli.transcode_first,
+        download_video,
+        max_video_size: cli.max_video_size,
+        vtt_export: cli.export_vtt,
+        compare: cli.compare,
+        include_segments: cli.include_segments,
+        no_transcript_cache: cli.no_cache,
+        show_progress: true,
+        done_links: done_links.unwrap_or_default(),
+    }
+        .with_post_processors(PostProcessor::defaults(replacements));
+
+    // an anonymous session must not replace a logged in one
+    let session_path = (!offline).then_some(session_path);
+    persist_session(&client, session_path.as_deref()).await?;
+
+    if let Some(Command::Estimate) = cli.command {
+        let estimate = client.estimate().await?;
+        let whisper_time = estimate.whisper_duration.mul_f64(whisper_realtime_factor);
+        println!("{} videos with captions (fast)", estimate.captioned);
+        println!(
+            "{} videos need whisper (slow): {} of audio, taking about {} at a real-time factor of {whisper_realtime_factor}",
+            estimate.whisper,
+            format_duration(estimate.whisper_duration),
+            format_duration(whisper_time),
+        );
+        if estimate.unknown_duration > 0 {
+            println!("{} videos needing whisper have an unknown duration and are not included", estimate.unknown_duration);
+        }
+        if estimate.failed > 0 {
+            println!("{} video configs could not be fetched", estimate.failed);
+        }
+
+        persist_session(&client, session_path.as_deref()).await?;
+        return Ok(());
+    }
+
+    if let Some(Command::PrintTranscript { video_url, source }) = cli.command {
+        let video_config = client.get_video_config(video_url.as_str()).await?;
+        let transcript = if source.captions_only {
+            client.get_caption_transcript(&video_config).await?
+        } else if source.force_whisper {
+            client.get_audio_transcript(&video_config).await?
+        } else {
+            client.get_transcript(&video_url, &video_config).await?
+        };
+        println!("{}", transcript.text());
 
-    let session_file = File::create(&session_path)?;
-    client.client.persist(&session_file).await?;
+        persist_session(&client, session_path.as_deref()).await?;
+        return Ok(());
+    }
 
-    let data = client.do_stuff().await?;
+    // results are written as the videos finish, only outputs spanning all videos keep the rows
+    let keep_rows = cli.by_pattern || cli.course_totals;
+    let mut data = vec![];
+    let rotation_limit = RotationLimit {
+        max_bytes: cli.max_csv_size,
+        max_rows: cli.max_csv_rows,
+    };
+    let mut writer = match (cli.format.csv(), cli.stdout) {
+        (false, _) => None,
+        (true, true) => Some(RotatingCsvWriter::stdout(header)?),
+        (true, false) if resuming => Some(RotatingCsvWriter::append(&cli.output, header)?),
+        (true, false) => Some(RotatingCsvWriter::new(&cli.output, header, rotation_limit)?),
+    };
+    let mut json_writer = (cli.format.json() || cli.include_segments)
+        .then(|| match (cli.format.json(), cli.stdout) {
+            (true, true) => JsonArrayWriter::stdout(cli.pretty),
+            _ if resuming => JsonArrayWriter::append("results.json", cli.pretty),
+            _ => JsonArrayWriter::create("results.json", cli.pretty),
+        })
+        .transpose()
+        .context("Failed to create results.json")?;
+    let mut shortened_writer = create_csv(&cli.short_output, &ShortenedDataRow::csv_header(defacto::pattern_names()), resuming)?;
+    let mut warnings_writer = create_csv(Path::new("results.warnings.csv"), &DataRow::warnings_csv_header(), resuming)?;
+    let mut timestamps_writer = cli.timestamps
+        .then(|| create_csv(Path::new("results.timestamps.csv"), &DataRow::timestamps_csv_header(), resuming))
+        .transpose()?;
+    let mut contexts_writer = cli.contexts
+        .then(|| create_csv(Path::new("results.contexts.csv"), &DataRow::contexts_csv_header(), resuming))
+        .transpose()?;
+    let mut database = cli.db.as_ref()
+        .map(Database::open)
+        .transpose()?;
+    let (mut written, mut changed) = (0, 0);
+    let mut summary = Summary::new();
 
-    client.client.persist(&session_file).await?;
-    let mut writer = csv::Writer::from_writer(File::create("results.csv")?);
-    let mut shortened_writer = csv::Writer::from_writer(File::create("results.short.csv")?);
-    for row in data {
-        writer.serialize(row.clone())?;
+    let mut write_row = |row: DataRow| -> anyhow::Result<()> {
+        if let Some(writer) = &mut writer {
+            writer.write_record(&row.csv_record(sanitize_csv, cli.max_transcript_chars))?;
+        }
+        if let Some(json_writer) = &mut json_writer {
+            json_writer.write(&row).context("Failed to write results.json")?;
+        }
+        for warning in row.warnings_csv_records() {
+            warnings_writer.write_record(&warning)?;
+        }
+        if let Some(timestamps_writer) = &mut timestamps_writer {
+            for found in row.timestamps_csv_records(timestamp_format) {
+                timestamps_writer.write_record(&found)?;
+            }
+        }
+        if let Some(contexts_writer) = &mut contexts_writer {
+            for context in row.contexts_csv_records(match_context_words) {
+                contexts_writer.write_record(&context)?;
+            }
+        }
+        if let Some(database) = &mut database {
+            if database.upsert(&row)? {
+                changed += 1;
+            }
+        }
+        written += 1;
+        summary.add(&row);
+        if keep_rows {
+            data.push(row.clone());
+        }
         let shortened_row: ShortenedDataRow = row.into();
-        shortened_writer.serialize(shortened_row)?
+        shortened_writer.write_record(&shortened_row.csv_record())?;
+        Ok(())
+    };
+    let run = async {
+        match cli.command {
+            Some(Command::Recount) => client.recount(&mut write_row).await,
+            _ => client.do_stuff(&mut write_row).await,
+        }
+    };
+    // on Ctrl-C the unfinished videos are dropped, finished rows and the session are still saved
+    let (report, interrupted) = tokio::select! {
+        report = run => (report?, false),
+        Ok(()) = tokio::signal::ctrl_c() => {
+            eprintln!("Interrupted, saving finished results and the session");
+            (RunReport::default(), true)
+        }
+    };
+    if let Some(writer) = &mut writer {
+        writer.flush()?;
+    }
+    if let Some(json_writer) = json_writer {
+        json_writer.finish().context("Failed to write results.json")?;
+    }
+    shortened_writer.flush()?;
+    warnings_writer.flush()?;
+    if let Some(timestamps_writer) = &mut timestamps_writer {
+        timestamps_writer.flush()?;
+    }
+    if let Some(contexts_writer) = &mut contexts_writer {
+        contexts_writer.flush()?;
     }
 
+    if cli.captions_only && !interrupted {
+        std::fs::write("results.captionless.txt", report.captionless.iter().map(|link| format!("{link}\n")).collect::<String>())?;
+        eprintln!("{} videos without captions listed in results.captionless.txt", report.captionless.len());
+    }
+    if !interrupted {
+        // with --stdout the results occupy stdout
+        if cli.stdout {
+            summary.print(&mut io::stderr(), &report, io::stderr().is_terminal())?;
+        } else {
+            summary.print(&mut io::stdout(), &report, io::stdout().is_terminal())?;
+        }
+    }
+    eprintln!("Downloaded {} in total", bandwidth::format_bytes(bandwidth::total()));
+
+    persist_session(&client, session_path.as_deref()).await?;
+    if let Some(db_path) = &cli.db {
+        tracing::info!("Updated {changed} of {written} videos in {}", db_path.display());
+    }
+
+    if cli.by_pattern {
+        output::write_by_pattern("results.by-pattern.csv", defacto::pattern_names(), &data)?;
+    }
+
+    if cli.course_totals {
+        let mut course_writer = csv::Writer::from_writer(File::create("results.course.csv")?);
+        course_writer.write_record(&ShortenedDataRow::csv_header(defacto::pattern_names()))?;
+        for course_row in DataRow::course_totals(data) {
+            let course_row: ShortenedDataRow = course_row.into();
+            course_writer.write_record(&course_row.csv_record())?;
+        }
+    }
+
+    if interrupted {
+        // transcriptions still running on blocking threads would keep the runtime from shutting down
+        std::process::exit(130);
+    }
     Ok(())
 
     // let result = get_enrolled_courses_by_timeline_classification::call(
@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use reqwest::header::HeaderMap;
+use reqwest::redirect::Policy;
+use sha2::{Digest, Sha256};
+
+/// Repository the whisper.cpp GGML models are published in
+const MODEL_BASE_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+
+/// Published whisper.cpp model sizes, larger ones transcribe better but slower
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ModelSize {
+    Tiny,
+    Base,
+    Small,
+    Medium,
+    Large,
+}
+
+impl ModelSize {
+    fn file_name(self) -> &'static str {
+        match self {
+            ModelSize::Tiny => "ggml-tiny.bin",
+            ModelSize::Base => "ggml-base.bin",
+            ModelSize::Small => "ggml-small.bin",
+            ModelSize::Medium => "ggml-medium.bin",
+            ModelSize::Large => "ggml-large-v3.bin",
+        }
+    }
+}
+
+/// Download a whisper model into `dir`, reusing a previous download if present
+///
+/// The file is verified against the SHA-256 huggingface reports for it in the `x-linked-etag`
+/// header and only moved to its final path once it matches.
+pub async fn download(size: ModelSize, dir: &Path) -> anyhow::Result<PathBuf> {
+    let model_path = dir.join(size.file_name());
+    if model_path.exists() {
+        tracing::info!("Using previously downloaded model {}", model_path.display());
+        return Ok(model_path);
+    }
+
+    let url = format!("{MODEL_BASE_URL}/{}", size.file_name());
+    let expected = expected_checksum(&url).await?;
+    tracing::info!("Downloading whisper model from: {url}");
+    let mut response = reqwest::get(&url).await?
+        .error_for_status()
+        .context("Failed to download whisper model")?;
+
+    let part_path = model_path.with_extension("bin.part");
+    let mut part_file = File::create(&part_path)?;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk);
+        part_file.write_all(&chunk)?;
+    }
+    part_file.flush()?;
+
+    let actual = hex::encode(hasher.finalize());
+    keep_verified(&part_path, &model_path, &expected, &actual)?;
+    Ok(model_path)
+}
+
+/// The SHA-256 of a model as huggingface reports it
+///
+/// The `x-linked-etag` header is only part of the redirect to the CDN, not of the CDN response
+/// the redirect leads to, so it is read from a request that does not follow it.
+async fn expected_checksum(url: &str) -> anyhow::Result<String> {
+    let client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()?;
+    let response = client.head(url)
+        .send().await?
+        .error_for_status()
+        .context("Failed to look up the checksum of the whisper model")?;
+    linked_etag(response.headers())
+        .ok_or(anyhow!("Model download carries no checksum to verify it against"))
+}
+
+/// The checksum in an `x-linked-etag` header, without quotes and in lowercase
+fn linked_etag(headers: &HeaderMap) -> Option<String> {
+    headers.get("x-linked-etag")
+        .and_then(|etag| etag.to_str().ok())
+        .map(|etag| etag.trim_matches('"').to_ascii_lowercase())
+}
+
+/// Move a finished download to `model_path` if its checksum matches, deleting it otherwise
+fn keep_verified(part_path: &Path, model_path: &Path, expected: &str, actual: &str) -> anyhow::Result<()> {
+    if actual != expected {
+        std::fs::remove_file(part_path)?;
+        return Err(anyhow!("Checksum mismatch of downloaded model, expected {expected} but got {actual}"));
+    }
+    std::fs::rename(part_path, model_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+    use super::*;
+
+    #[test]
+    fn linked_etag_is_unquoted_and_lowercase() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-linked-etag", HeaderValue::from_static("\"60ED5BC3DD14EEA856493D334349B405782DDCAF0028D4B5DF4088345FBA2EFE\""));
+        assert_eq!(
+            linked_etag(&headers).as_deref(),
+            Some("60ed5bc3dd14eea856493d334349b405782ddcaf0028d4b5df4088345fba2efe"),
+        );
+        assert_eq!(linked_etag(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn mismatching_download_is_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let part_path = dir.path().join("ggml-tiny.bin.part");
+        let model_path = dir.path().join("ggml-tiny.bin");
+        std::fs::write(&part_path, b"not a model").unwrap();
+
+        let err = keep_verified(&part_path, &model_path, "expected", "actual").unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"));
+        assert!(!part_path.exists());
+        assert!(!model_path.exists());
+    }
+
+    #[test]
+    fn matching_download_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let part_path = dir.path().join("ggml-tiny.bin.part");
+        let model_path = dir.path().join("ggml-tiny.bin");
+        std::fs::write(&part_path, b"model").unwrap();
+
+        keep_verified(&part_path, &model_path, "checksum", "checksum").unwrap();
+        assert!(!part_path.exists());
+        assert_eq!(std::fs::read(&model_path).unwrap(), b"model");
+    }
+}
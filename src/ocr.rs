@@ -0,0 +1,86 @@
+use std::path::Path;
+use std::time::Duration;
+use ffmpeg_next::format::{input, Pixel};
+use ffmpeg_next::software::scaling::{Context as Scaler, Flags};
+use ffmpeg_next::util::{frame::Video, media::Type};
+use tesseract::Tesseract;
+
+/// Minimum time between two recognized keyframes, slides rarely change faster than this
+const SLIDE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tesseract language model used for slides
+const OCR_LANGUAGE: &str = "deu";
+
+/// Recognize the text on the keyframes of a slide video, skipping consecutive repeats of a slide
+pub fn slide_texts(path: impl AsRef<Path>) -> anyhow::Result<Vec<String>> {
+    let mut ictx = input(&path)?;
+    let input = ictx
+        .streams()
+        .best(Type::Video)
+        .ok_or(ffmpeg_next::Error::StreamNotFound)?;
+    let stream_index = input.index();
+    let time_base = input.time_base();
+
+    let context_decoder = ffmpeg_next::codec::context::Context::from_parameters(input.parameters())?;
+    let mut decoder = context_decoder.decoder().video()?;
+    let mut scaler = Scaler::get(
+        decoder.format(), decoder.width(), decoder.height(),
+        Pixel::GRAY8, decoder.width(), decoder.height(),
+        Flags::BILINEAR,
+    )?;
+    let mut tesseract = Tesseract::new(None, Some(OCR_LANGUAGE))?;
+
+    let mut texts: Vec<String> = vec![];
+    let mut last_ms = None;
+    let interval_ms = SLIDE_INTERVAL.as_millis() as i64;
+    for (stream, packet) in ictx.packets() {
+        // keyframes decode on their own, so everything in between can be skipped
+        if stream.index() != stream_index || !packet.is_key() {
+            continue;
+        }
+        let ms = packet.pts()
+            .map(|pts| pts * 1000 * time_base.numerator() as i64 / time_base.denominator() as i64);
+        if let (Some(ms), Some(last_ms)) = (ms, last_ms) {
+            if ms - last_ms < interval_ms {
+                continue;
+            }
+        }
+        last_ms = ms.or(last_ms);
+
+        decoder.send_packet(&packet)?;
+        tesseract = recognize_frames(&mut decoder, &mut scaler, tesseract, &mut texts)?;
+    }
+    decoder.send_eof()?;
+    recognize_frames(&mut decoder, &mut scaler, tesseract, &mut texts)?;
+
+    Ok(texts)
+}
+
+/// Recognize all frames the decoder has ready, appending texts that differ from the previous one
+fn recognize_frames(
+    decoder: &mut ffmpeg_next::codec::decoder::Video,
+    scaler: &mut Scaler,
+    mut tesseract: Tesseract,
+    texts: &mut Vec<String>,
+) -> anyhow::Result<Tesseract> {
+    let mut decoded = Video::empty();
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        let mut gray = Video::empty();
+        scaler.run(&decoded, &mut gray)?;
+        tesseract = tesseract.set_frame(
+            gray.data(0),
+            gray.width() as i32,
+            gray.height() as i32,
+            1,
+            gray.stride(0) as i32,
+        )?;
+        let text = tesseract.get_text()?
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !text.is_empty() && texts.last() != Some(&text) {
+            texts.push(text);
+        }
+    }
+    Ok(tesseract)
+}
@@ -0,0 +1,444 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use csv::StringRecord;
+use serde::Serialize;
+use crate::defacto::DataRow;
+
+/// Formats the full results are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    Both,
+}
+
+impl OutputFormat {
+    pub fn csv(&self) -> bool {
+        matches!(self, Self::Csv | Self::Both)
+    }
+
+    pub fn json(&self) -> bool {
+        matches!(self, Self::Json | Self::Both)
+    }
+}
+
+/// Strip control characters other than newlines and tabs, which corrupt cells in some CSV readers
+pub fn sanitize_field(field: &str) -> Cow<'_, str> {
+    let is_stray = |c: char| c.is_control() && c != '\n' && c != '\r' && c != '\t';
+    if field.contains(is_stray) {
+        Cow::Owned(field.chars().filter(|&c| !is_stray(c)).collect())
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Cut a field to at most `max_chars` characters, marking the cut with a trailing ellipsis
+pub fn truncate_field(field: &str, max_chars: usize) -> Cow<'_, str> {
+    match field.char_indices().nth(max_chars) {
+        Some((end, _)) => Cow::Owned(format!("{}…", &field[..end])),
+        None => Cow::Borrowed(field),
+    }
+}
+
+/// Thresholds after which a [`RotatingCsvWriter`] starts a new part file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RotationLimit {
+    pub max_bytes: Option<u64>,
+    pub max_rows: Option<usize>,
+}
+
+impl RotationLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes.is_none() && self.max_rows.is_none()
+    }
+}
+
+/// Counts the bytes passed through to the wrapped writer
+#[derive(Debug)]
+struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Destination of a [`RotatingCsvWriter`] part or a [`JsonArrayWriter`]
+#[derive(Debug)]
+enum Output {
+    File(File),
+    Stdout(io::Stdout),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::File(file) => file.write(buf),
+            Output::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(file) => file.flush(),
+            Output::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+/// CSV writer that splits its output into `<name>.partNNN.csv` files once a [`RotationLimit`] is
+/// exceeded, repeating the header in every part
+///
+/// Without a limit it writes a single file at the given path.
+#[derive(Debug)]
+pub struct RotatingCsvWriter {
+    path: PathBuf,
+    header: StringRecord,
+    limit: RotationLimit,
+    part: usize,
+    rows: usize,
+    writer: csv::Writer<CountingWriter<Output>>,
+}
+
+impl RotatingCsvWriter {
+    pub fn new(path: impl AsRef<Path>, header: StringRecord, limit: RotationLimit) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let part = if limit.is_unlimited() { 0 } else { 1 };
+        let writer = Self::open(&Self::part_path(&path, part), &header)?;
+        Ok(Self {
+            path,
+            header,
+            limit,
+            part,
+            rows: 0,
+            writer,
+        })
+    }
+
+    /// Append to the file at `path`, e.g. to continue an earlier run, writing the header only if
+    /// the file is new or empty. Appending never rotates
+    pub fn append(path: impl AsRef<Path>, header: StringRecord) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        let mut writer = csv::Writer::from_writer(CountingWriter { inner: Output::File(file), written: 0 });
+        if is_empty {
+            writer.write_record(&header)?;
+        }
+        Ok(Self {
+            path,
+            header,
+            limit: RotationLimit::default(),
+            part: 0,
+            rows: 0,
+            writer,
+        })
+    }
+
+    /// Write everything to stdout instead of a file, without ever rotating
+    pub fn stdout(header: StringRecord) -> anyhow::Result<Self> {
+        let mut writer = csv::Writer::from_writer(CountingWriter { inner: Output::Stdout(io::stdout()), written: 0 });
+        writer.write_record(&header)?;
+        Ok(Self {
+            path: PathBuf::from("-"),
+            header,
+            limit: RotationLimit::default(),
+            part: 0,
+            rows: 0,
+            writer,
+        })
+    }
+
+    fn part_path(path: &Path, part: usize) -> PathBuf {
+        if part == 0 {
+            return path.to_path_buf();
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match path.extension() {
+            Some(extension) => format!("{stem}.part{part:03}.{}", extension.to_string_lossy()),
+            None => format!("{stem}.part{part:03}"),
+        };
+        path.with_file_name(file_name)
+    }
+
+    fn open(path: &Path, header: &StringRecord) -> anyhow::Result<csv::Writer<CountingWriter<Output>>> {
+        let file = File::create(path)?;
+        let mut writer = csv::Writer::from_writer(CountingWriter { inner: Output::File(file), written: 0 });
+        writer.write_record(header)?;
+        Ok(writer)
+    }
+
+    fn is_full(&mut self) -> anyhow::Result<bool> {
+        if self.rows == 0 {
+            return Ok(false);
+        }
+        if self.limit.max_rows.is_some_and(|max_rows| self.rows >= max_rows) {
+            return Ok(true);
+        }
+        if let Some(max_bytes) = self.limit.max_bytes {
+            self.writer.flush()?;
+            return Ok(self.writer.get_ref().written >= max_bytes);
+        }
+        Ok(false)
+    }
+
+    pub fn write_record(&mut self, record: &StringRecord) -> anyhow::Result<()> {
+        if self.is_full()? {
+            self.writer.flush()?;
+            self.part += 1;
+            self.rows = 0;
+            self.writer = Self::open(&Self::part_path(&self.path, self.part), &self.header)?;
+        }
+        self.writer.write_record(record)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The links of the rows an earlier run wrote to the CSV file at `path`
+///
+/// A missing or empty file has no rows yet. Returns `None` if the file has other columns than
+/// `header`, e.g. because patterns were changed since, as it can't be continued then.
+pub fn existing_links(path: &Path, header: &StringRecord) -> anyhow::Result<Option<HashSet<String>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some(HashSet::new())),
+        Err(err) => return Err(err).with_context(|| format!("Failed to open {}", path.display())),
+    };
+    let mut reader = csv::Reader::from_reader(file);
+    let existing_header = reader.headers()
+        .with_context(|| format!("Failed to read the header of {}", path.display()))?;
+    if existing_header.is_empty() {
+        return Ok(Some(HashSet::new()));
+    }
+    if existing_header != header {
+        return Ok(None);
+    }
+    let Some(link) = header.iter().position(|column| column == "link") else {
+        return Ok(None);
+    };
+
+    let mut links = HashSet::new();
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Failed to read the rows of {}", path.display()))?;
+        if let Some(link) = record.get(link) {
+            links.insert(link.to_string());
+        }
+    }
+    Ok(Some(links))
+}
+
+/// Continue the CSV file at `path` if it has the columns of `header`, otherwise start it over
+pub fn append_csv(path: &Path, header: &StringRecord) -> anyhow::Result<csv::Writer<File>> {
+    let file = match existing_links(path, header)? {
+        Some(_) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => File::create(path)?,
+    };
+    let is_empty = file.metadata()?.len() == 0;
+    let mut writer = csv::Writer::from_writer(file);
+    if is_empty {
+        writer.write_record(header)?;
+    }
+    Ok(writer)
+}
+
+/// Bytes at the end of a JSON array file searched for its closing bracket when continuing it
+const JSON_TAIL_BYTES: u64 = 64;
+
+/// Writes values one at a time as the elements of a JSON array, so they do not have to be kept
+/// until all of them are known
+#[derive(Debug)]
+pub struct JsonArrayWriter {
+    writer: BufWriter<Output>,
+    pretty: bool,
+    elements: usize,
+}
+
+impl JsonArrayWriter {
+    pub fn create(path: impl AsRef<Path>, pretty: bool) -> anyhow::Result<Self> {
+        Self::start(Output::File(File::create(path)?), pretty)
+    }
+
+    /// Write the array to stdout instead of a file
+    pub fn stdout(pretty: bool) -> anyhow::Result<Self> {
+        Self::start(Output::Stdout(io::stdout()), pretty)
+    }
+
+    fn start(output: Output, pretty: bool) -> anyhow::Result<Self> {
+        let mut writer = BufWriter::new(output);
+        writer.write_all(b"[")?;
+        Ok(Self {
+            writer,
+            pretty,
+            elements: 0,
+        })
+    }
+
+    /// Continue the array an earlier run wrote to `path` before its closing bracket, or create it
+    /// if there is none yet
+    pub fn append(path: impl AsRef<Path>, pretty: bool) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Self::create(path, pretty),
+            Err(err) => return Err(err.into()),
+        };
+        let len = file.metadata()?.len();
+        if len == 0 {
+            drop(file);
+            return Self::create(path, pretty);
+        }
+        // only whitespace follows the closing bracket, so the end of the file is enough to find it
+        let tail_start = len.saturating_sub(JSON_TAIL_BYTES);
+        file.seek(SeekFrom::Start(tail_start))?;
+        let mut tail = vec![];
+        file.read_to_end(&mut tail)?;
+        let not_an_array = || anyhow!("{} does not end in a JSON array, remove it or run without --resume", path.display());
+        let inner = tail.trim_ascii_end()
+            .strip_suffix(b"]")
+            .ok_or_else(not_an_array)?
+            .trim_ascii_end();
+        let Some(&last) = inner.last() else {
+            return Err(not_an_array());
+        };
+        file.set_len(tail_start + inner.len() as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            writer: BufWriter::new(Output::File(file)),
+            pretty,
+            // the element count only matters for the separators, so all earlier ones count as one
+            elements: usize::from(last != b'['),
+        })
+    }
+
+    pub fn write(&mut self, value: &impl Serialize) -> anyhow::Result<()> {
+        if self.elements > 0 {
+            self.writer.write_all(b",")?;
+        }
+        if self.pretty {
+            // newlines within strings are escaped, so every newline is indentation
+            let element = serde_json::to_string_pretty(value)?.replace('\n', "\n  ");
+            write!(self.writer, "\n  {element}")?;
+        } else {
+            serde_json::to_writer(&mut self.writer, value)?;
+        }
+        self.elements += 1;
+        Ok(())
+    }
+
+    /// Close the array and flush it
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if self.pretty && self.elements > 0 {
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.write_all(b"]\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Write a per-pattern ranking of all videos, ordered by descending match count
+pub fn write_by_pattern<'a>(path: impl AsRef<Path>, patterns: impl IntoIterator<Item = &'a str>, rows: &[DataRow]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["pattern", "rank", "title", "link", "count"])?;
+    for pattern in patterns {
+        let mut ranking = rows.iter()
+            .map(|row| (row, row.counts().get(pattern).copied().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        // stable sort keeps the input order for ties
+        ranking.sort_by(|(_, count_a), (_, count_b)| count_b.cmp(count_a));
+        for (rank, (row, count)) in ranking.into_iter().enumerate() {
+            writer.write_record([pattern, &(rank + 1).to_string(), row.title(), row.link(), &count.to_string()])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_run(path: &Path, pretty: bool, values: &[u32], append: bool) {
+        let mut writer = if append {
+            JsonArrayWriter::append(path, pretty).unwrap()
+        } else {
+            JsonArrayWriter::create(path, pretty).unwrap()
+        };
+        for value in values {
+            writer.write(value).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn read(path: &Path) -> Vec<u32> {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn control_characters_are_stripped_from_fields() {
+        assert_eq!(sanitize_field("de\0 facto\u{1b}[0m\r\n\tok"), "de facto[0m\r\n\tok");
+        assert!(matches!(sanitize_field("plain\ntext"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn sanitized_field_survives_a_csv_round_trip() {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record(["title", &sanitize_field("a\0b\x07c")]).unwrap();
+        let written = writer.into_inner().unwrap();
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(written.as_slice());
+        let record = reader.records().next().unwrap().unwrap();
+        assert_eq!(&record[1], "abc");
+    }
+
+    #[test]
+    fn resumed_json_array_is_continued() {
+        for pretty in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("results.json");
+            write_run(&path, pretty, &[1, 2], false);
+            write_run(&path, pretty, &[3], true);
+            write_run(&path, pretty, &[], true);
+            assert_eq!(read(&path), [1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn empty_or_missing_json_array_is_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.json");
+        write_run(&path, true, &[], true);
+        write_run(&path, true, &[1], true);
+        assert_eq!(read(&path), [1]);
+    }
+
+    #[test]
+    fn truncated_json_array_is_not_continued() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.json");
+        std::fs::write(&path, "[1,").unwrap();
+        assert!(JsonArrayWriter::append(&path, false).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[1,");
+    }
+}
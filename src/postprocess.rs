@@ -0,0 +1,115 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use indexmap::IndexMap;
+use crate::transcript::Transcript;
+
+/// A named transcript cleanup step applied to every segment text before matching
+#[derive(Clone)]
+pub struct PostProcessor {
+    name: String,
+    transform: Arc<dyn Fn(String) -> (String, usize) + Send + Sync>,
+}
+
+impl PostProcessor {
+    pub fn new(name: impl Into<String>, transform: impl Fn(String) -> String + Send + Sync + 'static) -> Self {
+        Self::counted(name, move |text| (transform(text), 0))
+    }
+
+    /// A stage whose `transform` also returns how many substitutions it made
+    pub fn counted(name: impl Into<String>, transform: impl Fn(String) -> (String, usize) + Send + Sync + 'static) -> Self {
+        Self {
+            name: name.into(),
+            transform: Arc::new(transform),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The transformed text and the number of substitutions made
+    pub fn apply(&self, text: String) -> (String, usize) {
+        (self.transform)(text)
+    }
+
+    /// Collapse runs of whitespace (including line breaks) into single spaces and trim the ends
+    pub fn normalize_whitespace() -> Self {
+        Self::new("normalize_whitespace", |text| {
+            text.split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+    }
+
+    /// Replace known mistranscriptions with their corrections, in order
+    pub fn replacements(replacements: IndexMap<String, String>) -> Self {
+        Self::counted("replacements", move |mut text| {
+            let mut substitutions = 0;
+            for (from, to) in &replacements {
+                let count = text.matches(from.as_str()).count();
+                if count > 0 {
+                    substitutions += count;
+                    text = text.replace(from.as_str(), to);
+                }
+            }
+            (text, substitutions)
+        })
+    }
+
+    /// The built-in stages in their default order
+    ///
+    /// Words hyphenated at caption line wraps are already rejoined while parsing captions (see
+    /// [`crate::transcript::join_lines`]), so there is no separate de-hyphenation stage.
+    pub fn defaults(replacements: IndexMap<String, String>) -> Vec<Self> {
+        vec![
+            Self::normalize_whitespace(),
+            Self::replacements(replacements),
+        ]
+    }
+}
+
+impl Debug for PostProcessor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PostProcessor")
+            .field(&self.name)
+            .finish()
+    }
+}
+
+/// Run every segment of `transcript` through `processors` in order, returning the number of
+/// substitutions made
+pub fn apply(processors: &[PostProcessor], transcript: &mut Transcript) -> usize {
+    let mut substitutions = 0;
+    for processor in processors {
+        tracing::trace!(stage = processor.name(), "Post-processing transcript");
+        for segment in &mut transcript.segments {
+            let (text, count) = processor.apply(std::mem::take(&mut segment.text));
+            segment.text = text;
+            substitutions += count;
+        }
+    }
+    substitutions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::Segment;
+
+    #[test]
+    fn substitutions_are_counted_over_all_segments() {
+        let replacements = IndexMap::from([
+            ("defakto".to_string(), "de facto".to_string()),
+            ("trivjal".to_string(), "trivial".to_string()),
+        ]);
+        let mut transcript = Transcript {
+            segments: vec![
+                Segment { start_ms: 0, end_ms: 1000, text: "defakto  trivjal,\ndefakto".to_string() },
+                Segment { start_ms: 1000, end_ms: 2000, text: "nichts".to_string() },
+                Segment { start_ms: 2000, end_ms: 3000, text: "trivjal".to_string() },
+            ],
+        };
+        assert_eq!(apply(&PostProcessor::defaults(replacements), &mut transcript), 4);
+        assert_eq!(transcript.text(), "de facto trivial, de facto nichts trivial");
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use reqwest::StatusCode;
+
+/// Delay before the first retry, doubled for every further one
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay before a retry, however many retries there were before
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Whether a request that failed without a response may succeed when sent again
+pub fn is_transient_error(err: &reqwest_middleware::Error) -> bool {
+    reqwest_error(err).is_some_and(|err| err.is_connect() || err.is_timeout() || err.is_request())
+}
+
+/// The error of the underlying request, which the HTTP cache middleware wraps in its own
+pub fn reqwest_error(err: &reqwest_middleware::Error) -> Option<&reqwest::Error> {
+    match err {
+        reqwest_middleware::Error::Reqwest(err) => Some(err),
+        reqwest_middleware::Error::Middleware(err) => err.chain().find_map(|err| err.downcast_ref()),
+    }
+}
+
+/// Whether a response status may turn into a success when the request is sent again, client
+/// errors like 401, 403 and 404 never do
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+}
+
+/// Delay before retry number `attempt`, counting from 0, with up to half of it added as jitter so
+/// concurrent requests do not retry in lockstep
+///
+/// The delay stops doubling at [`MAX_DELAY`].
+pub fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_DELAY);
+    // the sub-second clock is random enough to spread retries without pulling in an RNG
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or_default();
+    delay + delay.mul_f64(f64::from(nanos) / 2e9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_server_errors_are_retried() {
+        assert!(is_transient_status(StatusCode::BAD_GATEWAY));
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_transient_status(StatusCode::NOT_FOUND));
+        assert!(!is_transient_status(StatusCode::FORBIDDEN));
+        assert!(!is_transient_status(StatusCode::OK));
+    }
+
+    #[test]
+    fn backoff_doubles_with_up_to_half_as_jitter() {
+        for attempt in 0..5 {
+            let delay = BASE_DELAY * 2u32.pow(attempt);
+            let backoff = backoff(attempt);
+            assert!(backoff >= delay && backoff <= delay.mul_f64(1.5), "{backoff:?} for attempt {attempt}");
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        for attempt in [10, 40, u32::MAX] {
+            assert!(backoff(attempt) <= MAX_DELAY.mul_f64(1.5), "{:?} for attempt {attempt}", backoff(attempt));
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_errors_behind_middleware_errors_are_retried() {
+        // nothing listens on port 1
+        let err = reqwest::get("http://127.0.0.1:1/").await.unwrap_err();
+        assert!(err.is_connect());
+        let err = reqwest_middleware::Error::Middleware(anyhow::Error::new(err).context("Request error"));
+        assert!(reqwest_error(&err).is_some());
+        assert!(is_transient_error(&err));
+        assert!(!is_transient_error(&reqwest_middleware::Error::Middleware(anyhow::anyhow!("Cache unavailable"))));
+    }
+}
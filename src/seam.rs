@@ -0,0 +1,166 @@
+use std::ops::Range;
+use crate::config::SeamStrategy;
+use crate::transcript::Segment;
+
+/// A word of the overlapping region, located by its segment and position within it
+struct Word {
+    segment: usize,
+    index: usize,
+    normalized: String,
+}
+
+/// Append the segments of the next whisper chunk, resolving the audio both chunks transcribed
+///
+/// `overlap` is the time span covered by the end of the previous and the start of the next
+/// chunk. Without overlap the segments are simply appended.
+pub fn stitch(segments: &mut Vec<Segment>, next: Vec<Segment>, overlap: Range<u64>, strategy: SeamStrategy) {
+    if overlap.is_empty() || segments.is_empty() {
+        segments.extend(next);
+        return;
+    }
+
+    let next = match strategy {
+        SeamStrategy::Align => match align(segments, next, &overlap) {
+            Ok(()) => return,
+            Err(next) => {
+                tracing::debug!("No common words in the chunk overlap, cutting at its midpoint");
+                next
+            }
+        },
+        SeamStrategy::Midpoint => next,
+    };
+    cut_at_midpoint(segments, next, &overlap);
+}
+
+/// Keep the segments of the previous chunk starting before the middle of the overlap and those of
+/// the next chunk starting after it
+fn cut_at_midpoint(segments: &mut Vec<Segment>, next: Vec<Segment>, overlap: &Range<u64>) {
+    let cut = overlap.start + (overlap.end - overlap.start) / 2;
+    segments.retain(|segment| segment.start_ms < cut);
+    segments.extend(next.into_iter().filter(|segment| segment.start_ms >= cut));
+}
+
+/// Splice both chunks at the middle of the longest common word subsequence of the overlap,
+/// returning the untouched next segments if the overlap has no words in common
+fn align(segments: &mut Vec<Segment>, mut next: Vec<Segment>, overlap: &Range<u64>) -> Result<(), Vec<Segment>> {
+    let tail_start = segments.iter()
+        .position(|segment| segment.end_ms > overlap.start)
+        .unwrap_or(segments.len());
+    let head_end = next.iter()
+        .position(|segment| segment.start_ms >= overlap.end)
+        .unwrap_or(next.len());
+    let tail = words(&segments[tail_start..], tail_start);
+    let head = words(&next[..head_end], 0);
+
+    let pairs = common_subsequence(&tail, &head);
+    let Some(&(tail_word, head_word)) = pairs.get(pairs.len() / 2) else {
+        return Err(next);
+    };
+    let (tail_word, head_word) = (&tail[tail_word], &head[head_word]);
+
+    // the previous chunk keeps everything up to and including the splice word
+    segments.truncate(tail_word.segment + 1);
+    let last = &mut segments[tail_word.segment];
+    last.text = last.text.split_whitespace()
+        .take(tail_word.index + 1)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    // the next chunk continues right after it
+    next.drain(..head_word.segment);
+    let first = &mut next[0];
+    first.text = first.text.split_whitespace()
+        .skip(head_word.index + 1)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if first.text.is_empty() {
+        next.remove(0);
+    }
+
+    segments.extend(next);
+    Ok(())
+}
+
+/// The words of `segments`, with segment indices offset by `offset`
+fn words(segments: &[Segment], offset: usize) -> Vec<Word> {
+    segments.iter()
+        .enumerate()
+        .flat_map(|(segment, Segment { text, .. })| text.split_whitespace()
+            .enumerate()
+            .map(move |(index, word)| Word {
+                segment: segment + offset,
+                index,
+                normalized: word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase(),
+            }))
+        .filter(|word| !word.normalized.is_empty())
+        .collect()
+}
+
+/// Index pairs of a longest common subsequence of both word lists, in order
+fn common_subsequence(a: &[Word], b: &[Word]) -> Vec<(usize, usize)> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i].normalized == b[j].normalized {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].normalized == b[j].normalized {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_ms: u64, end_ms: u64, text: &str) -> Segment {
+        Segment { start_ms, end_ms, text: text.to_string() }
+    }
+
+    fn stitched(next: Vec<Segment>, strategy: SeamStrategy) -> Vec<String> {
+        let mut segments = vec![segment(0, 10_000, "hallo zusammen"), segment(28_000, 32_000, "das ist de facto so")];
+        stitch(&mut segments, next, 28_000..32_000, strategy);
+        segments.into_iter().map(|segment| segment.text).collect()
+    }
+
+    #[test]
+    fn midpoint_keeps_each_chunk_on_its_side_of_the_cut() {
+        let next = vec![segment(28_000, 30_000, "ist de facto so"), segment(30_000, 33_000, "und weiter")];
+        assert_eq!(stitched(next, SeamStrategy::Midpoint), ["hallo zusammen", "das ist de facto so", "und weiter"]);
+    }
+
+    #[test]
+    fn align_splices_at_the_common_words() {
+        let next = vec![segment(28_000, 30_000, "ist de Facto, so"), segment(30_000, 33_000, "und weiter")];
+        assert_eq!(stitched(next, SeamStrategy::Align), ["hallo zusammen", "das ist de facto", "so", "und weiter"]);
+    }
+
+    #[test]
+    fn align_without_common_words_cuts_at_the_midpoint() {
+        let next = vec![segment(28_000, 29_000, "ähm"), segment(30_000, 33_000, "und weiter")];
+        assert_eq!(stitched(next, SeamStrategy::Align), ["hallo zusammen", "das ist de facto so", "und weiter"]);
+    }
+
+    #[test]
+    fn chunks_without_overlap_are_appended() {
+        let mut segments = vec![segment(0, 1_000, "eins")];
+        stitch(&mut segments, vec![segment(1_000, 2_000, "zwei")], 1_000..1_000, SeamStrategy::Align);
+        assert_eq!(segments, [segment(0, 1_000, "eins"), segment(1_000, 2_000, "zwei")]);
+    }
+}
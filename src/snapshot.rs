@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{anyhow, Context};
+use reqwest::Url;
+
+/// Pages captured from a browser, used in place of live requests
+///
+/// A snapshot is either a directory of HTML files named after their url (see
+/// [`Snapshot::file_name`]) or a HAR export whose recorded responses are looked up by url.
+#[derive(Debug, Clone)]
+pub enum Snapshot {
+    Directory(PathBuf),
+    Har(Arc<HashMap<String, String>>),
+}
+
+impl Snapshot {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Ok(Self::Directory(path.to_path_buf()));
+        }
+
+        let har = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        let har = json::parse(&har).context("Failed to parse HAR snapshot json")?;
+        let pages = har["log"]["entries"].members()
+            .filter_map(|entry| Some((
+                entry["request"]["url"].as_str()?.to_string(),
+                entry["response"]["content"]["text"].as_str()?.to_string(),
+            )))
+            .collect::<HashMap<_, _>>();
+        tracing::info!("Loaded {} pages from HAR snapshot", pages.len());
+
+        Ok(Self::Har(Arc::new(pages)))
+    }
+
+    /// File name a page is expected under in a snapshot directory
+    ///
+    /// Host, path and query are kept with every other character replaced by `_`, so
+    /// `https://tuwel.tuwien.ac.at/mod/opencast/view.php?id=1` becomes
+    /// `tuwel.tuwien.ac.at_mod_opencast_view.php_id_1.html`.
+    pub fn file_name(url: &Url) -> String {
+        let mut name = url.host_str().unwrap_or_default().to_string();
+        name.push_str(url.path());
+        if let Some(query) = url.query() {
+            name.push('?');
+            name.push_str(query);
+        }
+        let name = name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+            .collect::<String>();
+        format!("{}.html", name.trim_end_matches('_'))
+    }
+
+    pub fn get(&self, url: &Url) -> anyhow::Result<String> {
+        match self {
+            Self::Directory(dir) => {
+                let path = dir.join(Self::file_name(url));
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("No snapshot of {url} at {}", path.display()))
+            }
+            Self::Har(pages) => pages.get(url.as_str())
+                .cloned()
+                .ok_or(anyhow!("No snapshot of {url} in HAR file")),
+        }
+    }
+}
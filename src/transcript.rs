@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+use crate::config::TimestampFormat;
+
+/// A piece of transcript text together with the time span it was spoken in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+}
+
+/// Where the text of a transcript came from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptSource {
+    /// A transcript attached to the episode
+    Attachment,
+    /// The Opencast search api
+    Search,
+    /// The published captions
+    Captions,
+    /// Transcribed with whisper
+    Whisper,
+    /// Cached by a version that did not record the source
+    #[default]
+    Unknown,
+}
+
+impl TranscriptSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            TranscriptSource::Attachment => "attachment",
+            TranscriptSource::Search => "search",
+            TranscriptSource::Captions => "captions",
+            TranscriptSource::Whisper => "whisper",
+            TranscriptSource::Unknown => "unknown",
+        }
+    }
+}
+
+/// A timestamped transcript made up of consecutive segments
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub segments: Vec<Segment>,
+}
+
+/// A caption segment of the Opencast text extraction JSON format, times in milliseconds
+#[derive(Deserialize, Clone, Debug)]
+struct JsonCaptionSegment {
+    start: u64,
+    #[serde(default)]
+    duration: u64,
+    text: String,
+}
+
+/// Opencast JSON captions, either a bare segment list or wrapped in a `segments` field
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum JsonCaptions {
+    Wrapped { segments: Vec<JsonCaptionSegment> },
+    Bare(Vec<JsonCaptionSegment>),
+}
+
+impl Transcript {
+    /// Parse captions published in the Opencast text extraction JSON format
+    pub fn from_json_captions(json: &str) -> anyhow::Result<Self> {
+        let (JsonCaptions::Wrapped { segments } | JsonCaptions::Bare(segments)) = serde_json::from_str(json)?;
+        let mut transcript = Transcript::default();
+        for segment in segments.into_iter().filter(|segment| !segment.text.trim().is_empty()) {
+            transcript.push_cue(segment.start, segment.start + segment.duration, segment.text.trim().to_string());
+        }
+        Ok(transcript)
+    }
+
+    /// Append a caption cue, merging it into the previous segment if it repeats it
+    ///
+    /// Rolling captions show a growing line, so a cue that extends the previous one replaces it and
+    /// a cue that the previous one already extends is dropped, only the longest of them is kept.
+    pub fn push_cue(&mut self, start_ms: u64, end_ms: u64, text: String) {
+        match self.segments.last_mut() {
+            Some(last) if extends(&last.text, &text) => last.end_ms = last.end_ms.max(end_ms),
+            Some(last) if extends(&text, &last.text) => {
+                last.end_ms = last.end_ms.max(end_ms);
+                last.text = text;
+            }
+            _ => self.segments.push(Segment { start_ms, end_ms, text }),
+        }
+    }
+
+    /// The plain transcript text with all segments joined by spaces
+    pub fn text(&self) -> String {
+        self.segments.iter()
+            .map(|segment| segment.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Render the segments as a WebVTT caption file, one cue per segment
+    pub fn to_vtt(&self) -> String {
+        let mut vtt = String::from("WEBVTT\n");
+        for segment in &self.segments {
+            vtt.push_str(&format!(
+                "\n{} --> {}\n{}\n",
+                vtt_timestamp(segment.start_ms),
+                vtt_timestamp(segment.end_ms),
+                segment.text.trim(),
+            ));
+        }
+        vtt
+    }
+
+    /// Time from the start of the first to the end of the last segment
+    pub fn duration_ms(&self) -> u64 {
+        match (self.segments.first(), self.segments.last()) {
+            (Some(first), Some(last)) => last.end_ms.saturating_sub(first.start_ms),
+            _ => 0,
+        }
+    }
+}
+
+/// Format milliseconds as a WebVTT `hh:mm:ss.ttt` timestamp
+fn vtt_timestamp(ms: u64) -> String {
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// Format milliseconds in the given output format
+pub fn format_timestamp(ms: u64, format: TimestampFormat) -> String {
+    match format {
+        TimestampFormat::Milliseconds => ms.to_string(),
+        TimestampFormat::Clock => vtt_timestamp(ms),
+        TimestampFormat::Seconds => format!("{}.{:03}", ms / 1000, ms % 1000),
+    }
+}
+
+/// Word level diff of two transcripts in `wdiff` notation, `[-removed-]` for words only in
+/// `captions` and `{+added+}` for words only in `whisper`, preceded by their similarity
+pub fn word_diff(captions: &str, whisper: &str) -> String {
+    let diff = TextDiff::from_words(captions, whisper);
+    let mut output = format!("similarity: {:.1}%\n\n", diff.ratio() * 100.0);
+    let mut open = ChangeTag::Equal;
+    for change in diff.iter_all_changes() {
+        if change.tag() != open {
+            output.push_str(closing_marker(open));
+            output.push_str(match change.tag() {
+                ChangeTag::Delete => "[-",
+                ChangeTag::Insert => "{+",
+                ChangeTag::Equal => "",
+            });
+            open = change.tag();
+        }
+        output.push_str(change.value());
+    }
+    output.push_str(closing_marker(open));
+    output.push('\n');
+    output
+}
+
+fn closing_marker(tag: ChangeTag) -> &'static str {
+    match tag {
+        ChangeTag::Delete => "-]",
+        ChangeTag::Insert => "+}",
+        ChangeTag::Equal => "",
+    }
+}
+
+/// Join the lines of a caption cue, rejoining words hyphenated at a line wrap
+///
+/// A trailing hyphen followed by a lowercase continuation (`"fak-"`, `"to"`) is removed and the
+/// word is joined (`"fakto"`). Before an uppercase continuation the hyphen is kept as part of a
+/// compound (`"E-"`, `"Mail"` becomes `"E-Mail"`).
+pub fn join_lines(lines: &[String]) -> String {
+    let mut text = String::new();
+    for line in lines.iter().map(|line| line.trim()).filter(|line| !line.is_empty()) {
+        let wrapped = text.strip_suffix('-')
+            .and_then(|rest| rest.chars().last())
+            .is_some_and(char::is_alphabetic);
+        if wrapped {
+            if line.starts_with(char::is_lowercase) {
+                text.pop();
+            }
+        } else if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(line);
+    }
+    text
+}
+
+/// Whether `text` starts with all words of `prefix`, which never splits a word of `text`
+fn extends(text: &str, prefix: &str) -> bool {
+    text.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// A single pattern match located in a transcript segment
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub pattern: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+    /// The whole caption cue or whisper segment the match occurred in
+    #[serde(default)]
+    pub snippet: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn timestamps_are_formatted_in_every_format() {
+        let ms = 3_723_456;
+        assert_eq!(format_timestamp(ms, TimestampFormat::Milliseconds), "3723456");
+        assert_eq!(format_timestamp(ms, TimestampFormat::Clock), "01:02:03.456");
+        assert_eq!(format_timestamp(ms, TimestampFormat::Seconds), "3723.456");
+        assert_eq!(format_timestamp(5, TimestampFormat::Seconds), "0.005");
+    }
+
+    #[test]
+    fn hyphenated_line_wraps_are_rejoined() {
+        assert_eq!(join_lines(&lines(&["Das ist de fak-", "to so"])), "Das ist de fakto so");
+        assert_eq!(join_lines(&lines(&["per E-", "Mail"])), "per E-Mail");
+    }
+
+    #[test]
+    fn lines_are_joined_by_single_spaces() {
+        assert_eq!(join_lines(&lines(&["  ergibt das ", "", "Sinn - ", "oder"])), "ergibt das Sinn - oder");
+        assert_eq!(join_lines(&lines(&[])), "");
+    }
+
+    fn segments(transcript: &Transcript) -> Vec<(u64, u64, &str)> {
+        transcript.segments.iter().map(|segment| (segment.start_ms, segment.end_ms, segment.text.as_str())).collect()
+    }
+
+    #[test]
+    fn rolling_cues_keep_only_the_longest() {
+        let mut transcript = Transcript::default();
+        transcript.push_cue(0, 1_000, "Das ist".to_string());
+        transcript.push_cue(1_000, 2_000, "Das ist trivial".to_string());
+        transcript.push_cue(2_000, 2_500, "Das ist".to_string());
+        transcript.push_cue(3_000, 4_000, "Ergibt das Sinn?".to_string());
+        assert_eq!(segments(&transcript), [(0, 2_500, "Das ist trivial"), (3_000, 4_000, "Ergibt das Sinn?")]);
+    }
+
+    #[test]
+    fn cues_continuing_a_word_are_not_merged() {
+        let mut transcript = Transcript::default();
+        transcript.push_cue(0, 1_000, "Das ist tri".to_string());
+        transcript.push_cue(1_000, 2_000, "Das ist trivial".to_string());
+        assert_eq!(transcript.segments.len(), 2);
+        assert!(extends("de facto so", "de facto"));
+        assert!(!extends("de factotum", "de facto"));
+    }
+}
@@ -0,0 +1,30 @@
+use std::cell::RefCell;
+use std::future::Future;
+
+tokio::task_local! {
+    static WARNINGS: RefCell<Vec<String>>;
+}
+
+/// Log a warning and record it for the video currently being processed
+macro_rules! data_warning {
+    ($($arg:tt)*) => {{
+        let warning = format!($($arg)*);
+        tracing::warn!("{warning}");
+        $crate::warnings::record(warning);
+    }};
+}
+pub(crate) use data_warning;
+
+/// Record a non-fatal issue if called within [`collect`]
+pub fn record(warning: String) {
+    let _ = WARNINGS.try_with(|warnings| warnings.borrow_mut().push(warning));
+}
+
+/// Run a future, collecting all warnings recorded while it runs
+pub async fn collect<F: Future>(future: F) -> (F::Output, Vec<String>) {
+    WARNINGS.scope(RefCell::new(Vec::new()), async {
+        let output = future.await;
+        let warnings = WARNINGS.with(|warnings| warnings.take());
+        (output, warnings)
+    }).await
+}